@@ -0,0 +1,130 @@
+// Copyright 2019 Brian Gianforcaro
+
+//! Compares the throughput of acquiring and releasing rundown protection
+//! with the atomic increment used by [`RundownRef`], against the compare
+//! and exchange loop it replaced, as the number of contending threads grows.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use run_down::RundownRef;
+use std::{
+    hint::black_box,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Barrier,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The numbers of threads contending on the same object.
+const THREAD_COUNTS: [usize; 7] = [1, 2, 4, 8, 16, 32, 64];
+
+/// The flag marking rundown as in progress, as laid out by [`RundownRef`].
+const RUNDOWN_IN_PROGRESS: u64 = 1 << 63;
+
+/// The previous implementation of acquiring and releasing protection, which
+/// retried a compare and exchange until it observed no concurrent change.
+#[derive(Default)]
+struct CasLoopRundown {
+    ref_count: AtomicU64,
+}
+
+impl CasLoopRundown {
+    fn try_acquire(&self) -> bool {
+        let mut current = self.ref_count.load(Ordering::Relaxed);
+
+        loop {
+            if current & RUNDOWN_IN_PROGRESS != 0 {
+                return false;
+            }
+
+            match self.ref_count.compare_exchange(
+                current,
+                current + 1,
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(new_current) => current = new_current,
+            }
+        }
+    }
+
+    fn release(&self) {
+        let mut current = self.ref_count.load(Ordering::Relaxed);
+
+        loop {
+            match self.ref_count.compare_exchange(
+                current,
+                current - 1,
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return,
+                Err(new_current) => current = new_current,
+            }
+        }
+    }
+}
+
+/// Runs `iterations` of `acquire_release` on each of `threads` threads at
+/// once, and returns the time taken until all of them finished.
+fn contended<F>(threads: usize, iterations: u64, acquire_release: F) -> Duration
+where
+    F: Fn() + Sync,
+{
+    let start = Barrier::new(threads + 1);
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                start.wait();
+                for _ in 0..iterations {
+                    acquire_release();
+                }
+            });
+        }
+
+        start.wait();
+        Instant::now()
+    })
+    .elapsed()
+}
+
+fn acquire_release(c: &mut Criterion) {
+    let mut group = c.benchmark_group("acquire_release");
+
+    for threads in THREAD_COUNTS {
+        group.bench_with_input(
+            BenchmarkId::new("fetch_add", threads),
+            &threads,
+            |b, &threads| {
+                let rundown = RundownRef::new();
+                b.iter_custom(|iterations| {
+                    contended(threads, iterations, || {
+                        black_box(rundown.try_acquire().unwrap());
+                    })
+                });
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("cas_loop", threads),
+            &threads,
+            |b, &threads| {
+                let rundown = CasLoopRundown::default();
+                b.iter_custom(|iterations| {
+                    contended(threads, iterations, || {
+                        assert!(black_box(rundown.try_acquire()));
+                        rundown.release();
+                    })
+                });
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, acquire_release);
+criterion_main!(benches);
@@ -0,0 +1,626 @@
+// Copyright 2019 Brian Gianforcaro
+
+#[cfg(feature = "async")]
+use crate::future::CacheAwareRundownFuture;
+use crate::{
+    event::LazyEvent, event::RundownEvent, flags::to_flags, flags::RundownFlags,
+    flags::RundownState, guard::CacheAwareRundownGuard, guard::OwnedCacheAwareRundownGuard,
+    rundown_ref::RundownError, rundown_ref::RundownSnapshot, rundown_ref::RundownWaitResult,
+    sync::AtomicU64, sync::Mutex, sync::MutexGuard, waiter::CacheAwareRundownWaiter,
+};
+use core::fmt;
+#[cfg(feature = "async")]
+use std::task::Waker;
+use std::{
+    num::NonZeroUsize, sync::atomic::AtomicUsize, sync::atomic::Ordering, sync::Arc,
+    sync::PoisonError, thread, time::Duration,
+};
+
+/// A reference count padded out to its own cache line, so that threads
+/// operating on different shards don't contend on the same cache line.
+#[repr(align(128))]
+#[derive(Default)]
+struct Shard {
+    /// The bit-packed reference count and flags of this shard, with the
+    /// same layout as the reference count of a [`crate::RundownRef`].
+    ref_count: AtomicU64,
+}
+
+/// Tracks the status of run-down protection for an object, like
+/// [`crate::RundownRef`], but spreads the reference count across
+/// multiple cache line sized shards.
+///
+/// Threads acquire and release protection on the shard assigned to them,
+/// so under heavy contention acquisition and release don't bounce a single
+/// cache line between cores. This is similar to `EX_RUNDOWN_REF_CACHE_AWARE`
+/// in the NT kernel. The trade-off is a larger memory footprint and a more
+/// expensive rundown, which needs to visit every shard.
+///
+/// The methods mirror those of [`crate::RundownRef`], so the two types can
+/// be swapped for each other.
+pub struct CacheAwareRundownRef {
+    /// The reference count shards, the number of shards is always a power of two.
+    shards: Box<[Shard]>,
+
+    /// The event used to signal the threads waiting for rundown that
+    /// rundown is now complete.
+    ///
+    /// The event is lazy initialized to avoid allocating the event
+    /// unless there is an active reference count when rundown starts.
+    event: LazyEvent,
+
+    /// Serializes marking the shards as 'rundown in progress' with
+    /// re-initialization, which would otherwise leave the shards partially
+    /// marked when a thread is still marking them as they are zeroed.
+    rundown_lock: Mutex<()>,
+}
+
+impl CacheAwareRundownRef {
+    /// Initializes a new [`CacheAwareRundownRef`], with a shard for
+    /// each CPU available to the process.
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        let parallelism = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        Self::with_shards(parallelism)
+    }
+
+    /// Initializes a new [`CacheAwareRundownRef`] with at least `count`
+    /// shards, the count is rounded up to the next power of two.
+    #[must_use]
+    pub fn with_shards(count: usize) -> Self {
+        let count = count.max(1).next_power_of_two();
+        Self {
+            shards: (0..count).map(|_| Shard::default()).collect(),
+            event: LazyEvent::new(),
+            rundown_lock: Mutex::new(()),
+        }
+    }
+
+    /// Re-initialize this instance so it can be used again. It is only valid
+    /// to call `re_init` once the object is completely run-down, via the
+    /// `wait_for_rundown` method.
+    ///
+    /// # Important
+    ///
+    /// The moment this method returns, new rundown protection requests can
+    /// succeed. You must perform all re-initialization of the shared object
+    /// the run-down protection is guarding before you call this method.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the object has not been run-down, or if there are still
+    /// outstanding references on the object.
+    pub fn re_init(&self) {
+        if let Err(error) = self.try_re_init() {
+            panic!("Attempt to re-init before rundown is complete: {:?}", error);
+        }
+    }
+
+    /// Re-initialize this instance so it can be used again, like `re_init`,
+    /// but returns an error instead of panicking if rundown is not complete.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err(RundownError::NotRunDown)` if rundown has not been
+    /// started, or `Err(RundownError::ReferencesOutstanding)` if rundown is
+    /// in progress but there are still outstanding references on the object.
+    ///
+    pub fn try_re_init(&self) -> Result<(), RundownError> {
+        // Rundown can't complete while the shards are being marked, and
+        // acquisitions never change a shard once it is marked, so the
+        // state can't change until the shards are zeroed.
+        let rundown_lock = self.lock_rundown();
+
+        match self.state() {
+            RundownState::Active => return Err(RundownError::NotRunDown),
+            RundownState::RundownInProgress => return Err(RundownError::ReferencesOutstanding),
+            RundownState::RundownComplete => {}
+        }
+
+        // Zero the reference count of every shard to make the object
+        // ready for use again.
+        for shard in &*self.shards {
+            shard.ref_count.store(0, Ordering::Release);
+        }
+
+        drop(rundown_lock);
+        Ok(())
+    }
+
+    /// Returns the current state of run-down protection, as
+    /// observed across all shards of this [`CacheAwareRundownRef`].
+    #[must_use]
+    pub fn state(&self) -> RundownState {
+        self.snapshot().state
+    }
+
+    /// Returns the number of references currently outstanding across all
+    /// shards of this [`CacheAwareRundownRef`].
+    #[must_use]
+    pub fn outstanding_refs(&self) -> u64 {
+        self.shards
+            .iter()
+            .map(|shard| Self::load_flags(shard).get_ref())
+            .sum()
+    }
+
+    /// Returns true if rundown has started, but there are still outstanding
+    /// references, so it has not completed yet.
+    #[must_use]
+    pub fn is_rundown_in_progress(&self) -> bool {
+        self.state() == RundownState::RundownInProgress
+    }
+
+    /// Returns true if every shard is marked as 'rundown in progress' and
+    /// there are no outstanding references on any shard.
+    #[must_use]
+    pub fn is_rundown_complete(&self) -> bool {
+        self.shards.iter().all(|shard| {
+            let current = Self::load_flags(shard);
+            current.is_rundown_in_progress() && current.is_ref_zero()
+        })
+    }
+
+    /// Returns the state of run-down protection on this [`CacheAwareRundownRef`]
+    /// and the number of outstanding references. Unlike [`crate::RundownRef`],
+    /// the shards are read one at a time, so the snapshot is only consistent
+    /// with each shard, not with a single point in time.
+    #[must_use]
+    pub fn snapshot(&self) -> RundownSnapshot {
+        let (mut all_marked, mut any_marked, mut outstanding_refs) = (true, false, 0);
+
+        for shard in &*self.shards {
+            let current = Self::load_flags(shard);
+            all_marked &= current.is_rundown_in_progress();
+            any_marked |= current.is_rundown_in_progress();
+            outstanding_refs += current.get_ref();
+        }
+
+        let state = if all_marked && outstanding_refs == 0 {
+            RundownState::RundownComplete
+        } else if any_marked {
+            RundownState::RundownInProgress
+        } else {
+            RundownState::Active
+        };
+
+        RundownSnapshot {
+            state,
+            outstanding_refs,
+        }
+    }
+
+    /// Attempts to acquire rundown protection on this [`CacheAwareRundownRef`],
+    /// returns the [`CacheAwareRundownGuard`] which holds the reference count,
+    /// or returns an error if the object is already being rundown.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err(RundownError::RundownInProgress)` if the rundown is
+    /// already in progress on the object, or `Err(RundownError::TooManyReferences)`
+    /// if the maximum number of references are outstanding on the shard.
+    ///
+    pub fn try_acquire(&self) -> Result<CacheAwareRundownGuard<'_>, RundownError> {
+        self.try_acquire_many(1)
+    }
+
+    /// Attempts to acquire `count` instances of rundown protection on the
+    /// shard of the calling thread in a single atomic operation. Returns the
+    /// [`CacheAwareRundownGuard`] which holds all of the reference counts,
+    /// or returns an error if the object is already being rundown.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err(RundownError::RundownInProgress)` if the rundown is
+    /// already in progress on the object, or `Err(RundownError::TooManyReferences)`
+    /// if acquiring `count` references would exceed the maximum of the shard.
+    ///
+    pub fn try_acquire_many(&self, count: u64) -> Result<CacheAwareRundownGuard<'_>, RundownError> {
+        let index = self.acquire(count)?;
+        Ok(CacheAwareRundownGuard::new(self, index, count))
+    }
+
+    /// Attempts to acquire rundown protection on this [`CacheAwareRundownRef`],
+    /// returns the [`OwnedCacheAwareRundownGuard`] which holds the reference
+    /// count, or returns an error if the object is already being rundown.
+    ///
+    /// Unlike the [`CacheAwareRundownGuard`] returned by `try_acquire`, the
+    /// owned guard holds a clone of the [`Arc`], so it can be moved to another
+    /// thread or held across an `.await` point.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err(RundownError::RundownInProgress)` if the rundown is
+    /// already in progress on the object, or `Err(RundownError::TooManyReferences)`
+    /// if the maximum number of references are outstanding on the shard.
+    ///
+    pub fn try_acquire_owned(
+        self: &Arc<Self>,
+    ) -> Result<OwnedCacheAwareRundownGuard, RundownError> {
+        let index = self.acquire(1)?;
+        Ok(OwnedCacheAwareRundownGuard::new(Arc::clone(self), index))
+    }
+
+    /// Acquires `count` reference counts on the shard of the calling thread
+    /// on behalf of a guard, and returns the index of the shard.
+    fn acquire(&self, count: u64) -> Result<usize, RundownError> {
+        let index = self.current_shard();
+        let shard = &self.shards[index];
+        let mut current = to_flags(shard.ref_count.load(Ordering::Relaxed));
+
+        loop {
+            if current.is_rundown_in_progress() {
+                return Err(RundownError::RundownInProgress);
+            }
+
+            let Some(new_bits_with_ref) = current.add_refs(count) else {
+                return Err(RundownError::TooManyReferences);
+            };
+
+            match Self::compare_exchange(shard, current.bits(), new_bits_with_ref) {
+                Ok(_) => return Ok(index),
+                Err(new_current) => current = to_flags(new_current),
+            }
+        }
+    }
+
+    /// Release previously acquired rundown protection.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no references are outstanding.
+    ///
+    pub fn release(&self) {
+        self.release_many(1);
+    }
+
+    /// Release previously acquired rundown protection, like `release`, but
+    /// returns an error instead of panicking if no references are outstanding.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err(RundownError::ReferenceUnderflow)` if no references
+    /// are outstanding, the reference counts are left unchanged.
+    ///
+    pub fn try_release(&self) -> Result<(), RundownError> {
+        self.try_release_many(1)
+    }
+
+    /// Release `count` instances of previously acquired rundown protection
+    /// in a single atomic operation, like `try_release_many`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no shard has `count` references outstanding.
+    ///
+    pub fn release_many(&self, count: u64) {
+        assert!(
+            self.try_release_many(count).is_ok(),
+            "Decrementing the reference-count would have under-flowed!"
+        );
+    }
+
+    /// Release `count` instances of previously acquired rundown protection
+    /// in a single atomic operation, returns an error if no shard has `count`
+    /// references outstanding.
+    ///
+    /// The references are released from the shard of the calling thread, or
+    /// from the next shard which has `count` references outstanding, so they
+    /// can be released by another thread than the one which acquired them.
+    /// References acquired together by `try_acquire_many` can always be
+    /// released together.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err(RundownError::ReferenceUnderflow)` if no shard has
+    /// `count` references outstanding, the reference counts are left unchanged.
+    ///
+    pub fn try_release_many(&self, count: u64) -> Result<(), RundownError> {
+        let first = self.current_shard();
+
+        // The number of shards is a power of two, so masking wraps around.
+        let released = (0..self.shards.len())
+            .map(|offset| (first + offset) & (self.shards.len() - 1))
+            .any(|index| self.release_shard(index, count));
+
+        if released {
+            Ok(())
+        } else {
+            Err(RundownError::ReferenceUnderflow)
+        }
+    }
+
+    /// Release `count` instances of rundown protection from the shard they
+    /// were acquired on. Returns false, leaving the shard unchanged, if fewer
+    /// than `count` references are outstanding on it.
+    fn release_shard(&self, index: usize, count: u64) -> bool {
+        self.release_from_shard(index, count, count) == count
+    }
+
+    /// Release the `count` instances of rundown protection owned by a guard,
+    /// which were acquired on the shard `index`.
+    ///
+    /// A reference released by hand is taken from the shard of the releasing
+    /// thread, which can be one acquired by a guard on that shard, while the
+    /// reference it stood for is left on another shard. The guard then
+    /// releases what remains on its own shard, and the rest from the others.
+    pub(crate) fn release_held(&self, index: usize, count: u64) {
+        if self.release_shard(index, count) {
+            return;
+        }
+
+        // The number of shards is a power of two, so masking wraps around.
+        let remaining = (0..self.shards.len())
+            .map(|offset| (index + offset) & (self.shards.len() - 1))
+            .fold(count, |remaining, index| {
+                remaining - self.release_from_shard(index, 1, remaining)
+            });
+
+        debug_assert_eq!(0, remaining, "the guards own the references they release");
+    }
+
+    /// Release up to `at_most` instances of rundown protection from the shard
+    /// `index`, returns the number released. Nothing is released if fewer than
+    /// `at_least` references are outstanding on the shard.
+    fn release_from_shard(&self, index: usize, at_least: u64, at_most: u64) -> u64 {
+        let shard = &self.shards[index];
+        let mut current = to_flags(shard.ref_count.load(Ordering::Relaxed));
+
+        let released = loop {
+            let released = current.get_ref().min(at_most);
+            if released < at_least {
+                return 0;
+            }
+
+            let bits_with_decrement = current.dec_refs(released);
+
+            match Self::compare_exchange(shard, current.bits(), bits_with_decrement) {
+                Ok(_) => {
+                    current = to_flags(bits_with_decrement);
+                    break released;
+                }
+                Err(new_current) => current = to_flags(new_current),
+            }
+        };
+
+        // Any release which drains a shard during rundown signals the waiters,
+        // they re-check every shard under the event lock. Checking the other
+        // shards here instead would race with the waiter marking them.
+        if current.is_ref_zero() && current.is_rundown_in_progress() {
+            if let Some(event) = self.event.get() {
+                event.set();
+            }
+        }
+
+        released
+    }
+
+    /// Returns true once the shard `index` has been marked as 'rundown in
+    /// progress', which happens before `begin_rundown` returns.
+    pub(crate) fn is_shard_rundown(&self, index: usize) -> bool {
+        Self::load_flags(&self.shards[index]).is_rundown_in_progress()
+    }
+
+    /// Blocks thread execution until there are no outstanding reference
+    /// counts taken on any shard of the [`CacheAwareRundownRef`], and every
+    /// shard has been marked as 'rundown in progress' to signal that no
+    /// other thread can safely acquire a reference count afterwards.
+    ///
+    /// # Important
+    ///
+    /// - Any number of threads can wait for rundown concurrently, they
+    ///   are all woken once the last outstanding reference is released.
+    ///
+    /// - This method is idempotent, it can be called multiple times.
+    ///
+    pub fn wait_for_rundown(&self) {
+        self.begin_rundown().wait();
+    }
+
+    /// Marks every shard of the [`CacheAwareRundownRef`] as 'rundown in
+    /// progress', so that no other thread can acquire a reference count
+    /// afterwards, without waiting for the outstanding references to be
+    /// released.
+    ///
+    /// Returns the [`CacheAwareRundownWaiter`] used to wait for the outstanding
+    /// references. Dropping the waiter doesn't affect the rundown.
+    ///
+    /// Like `wait_for_rundown`, this method is idempotent.
+    pub fn begin_rundown(&self) -> CacheAwareRundownWaiter<'_> {
+        self.mark_rundown_in_progress();
+        CacheAwareRundownWaiter::new(self)
+    }
+
+    /// Like `wait_for_rundown`, but gives up waiting once `timeout` has elapsed.
+    ///
+    /// Returns [`RundownWaitResult::Complete`] if rundown completed, otherwise
+    /// returns [`RundownWaitResult::TimedOut`] with the number of references
+    /// that were still outstanding across all shards.
+    ///
+    /// # Important
+    ///
+    /// - The object stays marked as 'rundown in progress' when the wait times
+    ///   out, so new protection requests will continue to fail.
+    ///
+    /// - The wait can be resumed by calling this method or `wait_for_rundown` again.
+    ///
+    pub fn wait_for_rundown_timeout(&self, timeout: Duration) -> RundownWaitResult {
+        self.begin_rundown().wait_timeout(timeout)
+    }
+
+    /// Returns a future which marks every shard as 'rundown in progress', and
+    /// completes once there are no outstanding references on any shard, like
+    /// `RundownRef::wait_for_rundown_async`.
+    ///
+    /// The shards are marked when the future is first polled, not when
+    /// this method is called.
+    #[cfg(feature = "async")]
+    pub const fn wait_for_rundown_async(&self) -> CacheAwareRundownFuture<'_> {
+        CacheAwareRundownFuture::new(self)
+    }
+
+    /// Drives the [`CacheAwareRundownFuture`] returned by `wait_for_rundown_async`,
+    /// returns true once rundown is complete, otherwise registers the waker
+    /// on the event, keeping the registration in `task`.
+    #[cfg(feature = "async")]
+    pub(crate) fn poll_rundown(&self, task: &mut Option<u64>, waker: &Waker) -> bool {
+        self.mark_rundown_in_progress();
+
+        !self.is_rundown_pending()
+            || !self
+                .event()
+                .register_while(task, waker, || self.is_rundown_pending())
+    }
+
+    /// Removes the registration `task` of a [`CacheAwareRundownFuture`]
+    /// which is dropped, so its waker isn't kept until rundown completes.
+    #[cfg(feature = "async")]
+    pub(crate) fn cancel_poll(&self, task: u64) {
+        if let Some(event) = self.event.get() {
+            event.unregister(task);
+        }
+    }
+
+    /// Blocks thread execution until no shard which is marked as 'rundown
+    /// in progress' has outstanding references, for at most `timeout` if one
+    /// is provided. Returns false if the timeout elapsed first.
+    pub(crate) fn wait_for_completion(&self, timeout: Option<Duration>) -> bool {
+        // The event only needs to be created if there is something to wait for.
+        if !self.is_rundown_pending() {
+            return true;
+        }
+
+        timeout.map_or_else(
+            || {
+                self.event().wait_while(|| self.is_rundown_pending());
+                true
+            },
+            |timeout| {
+                self.event()
+                    .wait_while_timeout(timeout, || self.is_rundown_pending())
+            },
+        )
+    }
+
+    /// Returns the outcome of waiting for the rundown in progress, as of now.
+    pub(crate) fn wait_result(&self) -> RundownWaitResult {
+        // Only the references on marked shards hold up the rundown, the
+        // shards are unmarked once the object is re-initialized.
+        let outstanding = self
+            .shards
+            .iter()
+            .map(Self::load_flags)
+            .filter(|current| current.is_rundown_in_progress())
+            .map(RundownFlags::get_ref)
+            .sum();
+
+        match outstanding {
+            0 => RundownWaitResult::Complete,
+            outstanding => RundownWaitResult::TimedOut { outstanding },
+        }
+    }
+
+    /// Turns on the rundown bit of every shard.
+    fn mark_rundown_in_progress(&self) {
+        let rundown_lock = self.lock_rundown();
+
+        for shard in &*self.shards {
+            let mut current = to_flags(shard.ref_count.load(Ordering::Relaxed));
+
+            loop {
+                // The event needs to exist before the last shard is marked,
+                // as from then on a release can observe rundown completion.
+                if current.is_ref_active() {
+                    self.event();
+                }
+
+                let bits_with_rundown = current.set_rundown_in_progress();
+
+                match Self::compare_exchange(shard, current.bits(), bits_with_rundown) {
+                    Ok(_) => break,
+                    Err(new_current) => current = to_flags(new_current),
+                }
+            }
+        }
+
+        drop(rundown_lock);
+    }
+
+    /// Returns true while any shard which is marked as 'rundown in progress'
+    /// still has outstanding references. Unlike checking for completion,
+    /// this can't be confused by a concurrent re-initialization, which
+    /// un-marks the shards one at a time.
+    fn is_rundown_pending(&self) -> bool {
+        self.shards.iter().any(|shard| {
+            let current = Self::load_flags(shard);
+            current.is_rundown_in_progress() && current.is_ref_active()
+        })
+    }
+
+    /// Returns the index of the shard assigned to the calling thread.
+    #[inline]
+    fn current_shard(&self) -> usize {
+        /// The source of shard hints for threads, handed out round robin.
+        static NEXT_SHARD_HINT: AtomicUsize = AtomicUsize::new(0);
+
+        thread_local! {
+            static SHARD_HINT: usize = NEXT_SHARD_HINT.fetch_add(1, Ordering::Relaxed);
+        }
+
+        // The number of shards is a power of two, so masking the hint
+        // is enough to map it to one of the shards.
+        SHARD_HINT.with(|hint| hint & (self.shards.len() - 1))
+    }
+
+    /// Acquires the lock serializing marking the shards with re-initialization.
+    /// The lock protects no data, so poisoning is ignored.
+    fn lock_rundown(&self) -> MutexGuard<'_, ()> {
+        self.rundown_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns the event used to signal rundown completion, lazily
+    /// creating it if it has not been created yet.
+    #[inline]
+    fn event(&self) -> &RundownEvent {
+        self.event.get_or_create()
+    }
+
+    /// Load the current flags of a shard atomically.
+    #[inline]
+    fn load_flags(shard: &Shard) -> RundownFlags {
+        to_flags(shard.ref_count.load(Ordering::Acquire))
+    }
+
+    /// Readability wrapper around atomic compare exchange.
+    #[inline]
+    fn compare_exchange(shard: &Shard, current: u64, new: u64) -> Result<u64, u64> {
+        // Acquire the lazily created event along with the rundown flag, and
+        // release the reference count changes to the waiter.
+        let success_ord = Ordering::AcqRel;
+        let failure_ord = Ordering::Relaxed;
+        shard
+            .ref_count
+            .compare_exchange(current, new, success_ord, failure_ord)
+    }
+}
+
+impl Default for CacheAwareRundownRef {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for CacheAwareRundownRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let snapshot = self.snapshot();
+
+        f.debug_struct("CacheAwareRundownRef")
+            .field("state", &snapshot.state)
+            .field("outstanding_refs", &snapshot.outstanding_refs)
+            .field("shards", &self.shards.len())
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,101 @@
+// Copyright 2019 Brian Gianforcaro
+
+//! A global table of the callbacks registered to run once rundown completes,
+//! keyed by the address of the object, so the object doesn't need space to
+//! hold them itself.
+//!
+//! The object flags that callbacks are registered in its reference count, so
+//! the table is only locked by rundowns which have callbacks to run.
+
+use crate::{
+    sync::{Mutex, MutexGuard},
+    table::{address_table, Entries, Key},
+};
+use std::cell::RefCell;
+
+/// A callback registered to run once rundown completes.
+pub type Callback = Box<dyn FnOnce() + Send>;
+
+address_table! {
+    /// The callbacks registered on objects, indexed by the bucket of their address.
+    static CALLBACKS: Mutex<Entries<Callback>> = Mutex::new(Entries::new());
+}
+
+// The keys of the objects whose callbacks the thread is running, innermost
+// last, so that re-initializing the object from a callback can be detected.
+#[cfg(not(feature = "loom"))]
+std::thread_local! {
+    static RUNNING: RefCell<Vec<Key>> = const { RefCell::new(Vec::new()) };
+}
+
+// The loom threads all run on the same thread of the test.
+#[cfg(feature = "loom")]
+loom::thread_local! {
+    static RUNNING: RefCell<Vec<Key>> = RefCell::new(Vec::new());
+}
+
+/// The callbacks registered on an object, locked so the registered
+/// callbacks can be changed along with the flags of the object.
+pub struct Registered {
+    /// The key of the object in the table.
+    key: Key,
+
+    /// The locked bucket holding the callbacks of the object.
+    bucket: MutexGuard<'static, Entries<Callback>>,
+}
+
+impl Registered {
+    /// Registers `callback` on the object.
+    pub fn push(&mut self, callback: Callback) {
+        self.bucket.insert(self.key, callback);
+    }
+
+    /// Removes and returns all of the callbacks registered on the object,
+    /// in the order they were registered in.
+    pub fn take(&mut self) -> Vec<Callback> {
+        self.bucket.take(self.key)
+    }
+}
+
+/// Marks the calling thread as running the callbacks of an object,
+/// until this structure is dropped.
+pub struct Running {
+    /// The key of the object in the table.
+    key: Key,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        RUNNING.with(|running| {
+            let mut running = running.borrow_mut();
+
+            if let Some(index) = running.iter().rposition(|key| *key == self.key) {
+                running.remove(index);
+            }
+        });
+    }
+}
+
+/// Marks the calling thread as running the callbacks of `object`.
+pub fn enter<T>(object: &T) -> Running {
+    let key = Key::of(object);
+    RUNNING.with(|running| running.borrow_mut().push(key));
+
+    Running { key }
+}
+
+/// Returns true if the calling thread is running the callbacks of `object`.
+pub fn is_running<T>(object: &T) -> bool {
+    let key = Key::of(object);
+    RUNNING.with(|running| running.borrow().contains(&key))
+}
+
+/// Locks the callbacks registered on `object`.
+pub fn lock<T>(object: &T) -> Registered {
+    let key = Key::of(object);
+
+    Registered {
+        key,
+        bucket: key.lock(&CALLBACKS),
+    }
+}
@@ -0,0 +1,197 @@
+// Copyright 2019 Brian Gianforcaro
+
+use crate::{
+    flags::RundownState, guard::RundownGuard, rundown_ref::RundownError, rundown_ref::RundownRef,
+    sync::AtomicBool,
+};
+use core::{cell::UnsafeCell, fmt, mem::ManuallyDrop, ops::Deref, sync::atomic::Ordering};
+
+/// A container which owns a value alongside the [`RundownRef`] protecting
+/// it, and drops the value as soon as it has been retired and the last
+/// guard on it is gone, without any thread waiting for rundown.
+///
+/// Shared access is obtained with `try_acquire`, which fails once `retire`
+/// has been called. The value is dropped exactly once, by whichever happens
+/// last, the call to `retire` or the drop of the last [`DropOnRundownGuard`].
+///
+/// # Example
+///
+/// ```rust
+/// use run_down::DropOnRundown;
+///
+/// let connection = DropOnRundown::new(String::from("connection"));
+///
+/// let guard = connection.try_acquire().unwrap();
+/// connection.retire();
+/// assert!(connection.try_acquire().is_err());
+///
+/// // The value is still reachable through the outstanding guard,
+/// // and is dropped along with the guard.
+/// assert_eq!("connection", guard.as_str());
+/// drop(guard);
+/// assert!(connection.is_dropped());
+/// ```
+pub struct DropOnRundown<T> {
+    /// The run-down reference protecting access to the data.
+    protection: RundownRef,
+
+    /// Set by the thread which drops the data, once rundown is complete.
+    dropped: AtomicBool,
+
+    /// The data protected by the run-down reference, dropped by hand.
+    data: UnsafeCell<ManuallyDrop<T>>,
+}
+
+// Safety: The data is shared between threads through `try_acquire`, and is
+// dropped by whichever thread releases the last guard, so the data must be
+// both `Send` and `Sync`.
+unsafe impl<T: Send + Sync> Sync for DropOnRundown<T> {}
+
+impl<T> DropOnRundown<T> {
+    const_fn! {
+        /// Initializes a new [`DropOnRundown`] protecting the provided value.
+        #[inline]
+        #[must_use]
+        pub fn new(data: T) -> Self {
+            Self {
+                protection: RundownRef::new(),
+                dropped: AtomicBool::new(false),
+                data: UnsafeCell::new(ManuallyDrop::new(data)),
+            }
+        }
+    }
+
+    /// Attempts to acquire rundown protection on the data, returns the
+    /// [`DropOnRundownGuard`] which dereferences to the data, or returns
+    /// an error if the data has been retired.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err(RundownError::RundownInProgress)` if the data has been
+    /// retired, or `Err(RundownError::TooManyReferences)` if the maximum number
+    /// of references are already outstanding.
+    ///
+    pub fn try_acquire(&self) -> Result<DropOnRundownGuard<'_, T>, RundownError> {
+        match self.protection.try_acquire() {
+            Ok(guard) => Ok(DropOnRundownGuard {
+                owner: self,
+                guard: ManuallyDrop::new(guard),
+            }),
+            Err(error) => {
+                // The failed acquisition backed out its reference, which
+                // completes rundown instead of the last guard if they raced.
+                self.drop_if_run_down();
+                Err(error)
+            }
+        }
+    }
+
+    /// Retires the data, so that no further protection can be acquired on it.
+    /// The data is dropped before this method returns if no guards are
+    /// outstanding, otherwise it is dropped along with the last guard.
+    ///
+    /// This method doesn't block, and it is idempotent.
+    pub fn retire(&self) {
+        let _ = self.protection.begin_rundown();
+
+        self.drop_if_run_down();
+    }
+
+    /// Returns the current state of run-down protection on the data.
+    #[inline]
+    #[must_use]
+    pub fn state(&self) -> RundownState {
+        self.protection.state()
+    }
+
+    /// Returns true once the data has been retired and dropped.
+    #[inline]
+    #[must_use]
+    pub fn is_dropped(&self) -> bool {
+        self.dropped.load(Ordering::Acquire)
+    }
+
+    /// Drops the data if rundown is complete, and no other thread
+    /// dropped it already.
+    fn drop_if_run_down(&self) {
+        // Observing rundown as complete synchronizes with the releases of
+        // all guards, and no guards can be acquired once it is complete.
+        if self.protection.is_rundown_complete() && !self.dropped.swap(true, Ordering::AcqRel) {
+            // Safety: Rundown is complete, so no guards are outstanding and
+            // none can be acquired, and the swap ensures only this thread
+            // drops the data.
+            unsafe { ManuallyDrop::drop(&mut *self.data.get()) }
+        }
+    }
+}
+
+impl<T> Drop for DropOnRundown<T> {
+    fn drop(&mut self) {
+        // The data was never retired, or guards were leaked so rundown
+        // never completed, either way nothing can access the data anymore.
+        if !self.dropped.load(Ordering::Acquire) {
+            // Safety: The data has not been dropped, and the mutable
+            // borrow guarantees no other access exists.
+            unsafe { ManuallyDrop::drop(self.data.get_mut()) }
+        }
+    }
+}
+
+impl<T: Default> Default for DropOnRundown<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> fmt::Debug for DropOnRundown<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DropOnRundown")
+            .field("protection", &self.protection)
+            .field("dropped", &self.is_dropped())
+            .finish_non_exhaustive()
+    }
+}
+
+/// An RAII guard providing shared access to the data in a [`DropOnRundown`].
+///
+/// The guard holds run-down protection on the data, which is released when
+/// the guard is dropped. If the data was retired, dropping the last guard
+/// drops the data as well.
+///
+/// This structure is created by the `try_acquire` method on `DropOnRundown`.
+pub struct DropOnRundownGuard<'r, T> {
+    /// The container holding the data protected by the guard.
+    owner: &'r DropOnRundown<T>,
+
+    /// The run-down protection held on behalf of this guard, which must be
+    /// released before the data can be dropped.
+    guard: ManuallyDrop<RundownGuard<'r>>,
+}
+
+impl<T> Deref for DropOnRundownGuard<'_, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        // Safety: The data is only dropped once rundown is complete,
+        // which can't happen while this guard is outstanding.
+        unsafe { &*self.owner.data.get() }
+    }
+}
+
+impl<T> Drop for DropOnRundownGuard<'_, T> {
+    fn drop(&mut self) {
+        // Safety: The guard is never used again.
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+
+        self.owner.drop_if_run_down();
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for DropOnRundownGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DropOnRundownGuard")
+            .field("data", &**self)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,259 @@
+// Copyright 2019 Brian Gianforcaro
+
+use crate::sync::{AtomicPtr, Condvar, Mutex, MutexGuard};
+#[cfg(feature = "async")]
+use std::task::Waker;
+use std::{ptr, sync::atomic::Ordering, sync::PoisonError, time::Duration, time::Instant};
+
+/// The tasks waiting on a [`RundownEvent`], protected by its lock.
+#[derive(Default)]
+struct Waiters {
+    /// The identifier handed to the next task which registers.
+    #[cfg(feature = "async")]
+    next_id: u64,
+
+    /// The wakers of the tasks awaiting a [`crate::RundownFuture`],
+    /// along with the identifier of their registration.
+    #[cfg(feature = "async")]
+    wakers: Vec<(u64, Waker)>,
+}
+
+/// The event used to signal the threads waiting for rundown that
+/// rundown is now complete.
+///
+/// The event has no state of its own, waiters block until a condition
+/// on the reference count is satisfied. The condition is evaluated with
+/// the event locked, and the event is signaled with the event locked,
+/// so any number of threads can wait on the event without missing the
+/// signal, regardless of how the reference count changes in between.
+#[derive(Default)]
+pub struct RundownEvent {
+    /// The lock serializing waiters with the signaling thread.
+    waiters: Mutex<Waiters>,
+
+    /// The condition variable the waiting threads block on.
+    condvar: Condvar,
+}
+
+impl RundownEvent {
+    const_fn! {
+        /// Creates an event without any waiters, in a const context, so
+        /// it can be placed in a static.
+        #[cfg(any(not(target_os = "linux"), feature = "loom"))]
+        pub fn new() -> Self {
+            Self {
+                waiters: Mutex::new(Waiters {
+                    #[cfg(feature = "async")]
+                    next_id: 0,
+                    #[cfg(feature = "async")]
+                    wakers: Vec::new(),
+                }),
+                condvar: Condvar::new(),
+            }
+        }
+    }
+
+    /// Blocks thread execution while `condition` returns true.
+    pub fn wait_while<F>(&self, mut condition: F)
+    where
+        F: FnMut() -> bool,
+    {
+        let mut waiters = self.lock();
+
+        while condition() {
+            waiters = self
+                .condvar
+                .wait(waiters)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+
+        drop(waiters);
+    }
+
+    /// Blocks thread execution while `condition` returns true, for at most
+    /// `timeout`. Returns true if the wait ended because the condition no
+    /// longer held, or false if the timeout elapsed first.
+    pub fn wait_while_timeout<F>(&self, timeout: Duration, mut condition: F) -> bool
+    where
+        F: FnMut() -> bool,
+    {
+        let deadline = Instant::now().checked_add(timeout);
+        let mut waiters = self.lock();
+
+        while condition() {
+            let remaining = deadline.map_or(timeout, |deadline| {
+                deadline.saturating_duration_since(Instant::now())
+            });
+
+            if remaining.is_zero() {
+                return false;
+            }
+
+            waiters = self
+                .condvar
+                .wait_timeout(waiters, remaining)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+
+        drop(waiters);
+        true
+    }
+
+    /// Registers `waker` to be woken when the event is signaled, if
+    /// `condition` returns true. Returns the result of the condition.
+    ///
+    /// The registration of the task is kept in `task`, so polling again
+    /// only updates its waker, and `unregister` can remove it once the
+    /// task stops waiting.
+    #[cfg(feature = "async")]
+    pub fn register_while<F>(&self, task: &mut Option<u64>, waker: &Waker, condition: F) -> bool
+    where
+        F: FnOnce() -> bool,
+    {
+        let mut waiters = self.lock();
+
+        if !condition() {
+            return false;
+        }
+
+        // The registration is gone once the event was signaled.
+        let registered = task.and_then(|id| {
+            waiters
+                .wakers
+                .iter_mut()
+                .find(|registered| registered.0 == id)
+        });
+
+        if let Some(registered) = registered {
+            if !registered.1.will_wake(waker) {
+                registered.1.clone_from(waker);
+            }
+        } else {
+            let id = waiters.next_id;
+            waiters.next_id += 1;
+            waiters.wakers.push((id, waker.clone()));
+            *task = Some(id);
+        }
+
+        drop(waiters);
+        true
+    }
+
+    /// Removes the registration `task`, unless the event was signaled.
+    #[cfg(feature = "async")]
+    pub fn unregister(&self, task: u64) {
+        let mut waiters = self.lock();
+
+        if let Some(index) = waiters
+            .wakers
+            .iter()
+            .position(|registered| registered.0 == task)
+        {
+            // Drop the waker once the event is unlocked.
+            let registered = waiters.wakers.swap_remove(index);
+            drop(waiters);
+            drop(registered);
+        }
+    }
+
+    /// Wakes all threads and tasks waiting on the event, so they
+    /// re-evaluate their wait condition.
+    pub fn set(&self) {
+        // The lock must be acquired even if there are no wakers to take, so
+        // a waiter which is evaluating its condition is either blocked before
+        // the notification is sent, or observes the change in its condition.
+        #[cfg(feature = "async")]
+        let wakers = std::mem::take(&mut self.lock().wakers);
+        #[cfg(not(feature = "async"))]
+        drop(self.lock());
+
+        self.condvar.notify_all();
+
+        #[cfg(feature = "async")]
+        for (_, waker) in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Acquires the event lock. The lock only guards the list of waiters,
+    /// which is always left in a consistent state, so poisoning is ignored.
+    fn lock(&self) -> MutexGuard<'_, Waiters> {
+        self.waiters.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A [`RundownEvent`] which is only allocated the first time it is needed.
+///
+/// Once created, the event lives as long as the [`LazyEvent`], so
+/// references to it can be handed out without any further synchronization.
+pub struct LazyEvent {
+    /// The event, or null if it has not been created yet.
+    event: AtomicPtr<RundownEvent>,
+}
+
+impl LazyEvent {
+    const_fn! {
+        /// Initializes a new [`LazyEvent`], without creating the event.
+        pub fn new() -> Self {
+            Self {
+                event: AtomicPtr::new(ptr::null_mut()),
+            }
+        }
+    }
+
+    /// Returns the event if it has already been created.
+    #[inline]
+    pub fn get(&self) -> Option<&RundownEvent> {
+        // Safety: A non-null pointer always points to the event, which is
+        // only freed when the `LazyEvent` is dropped.
+        unsafe { self.event.load(Ordering::Acquire).as_ref() }
+    }
+
+    /// Returns the event, creating it if it has not been created yet.
+    pub fn get_or_create(&self) -> &RundownEvent {
+        if let Some(event) = self.get() {
+            return event;
+        }
+
+        let new_event = Box::into_raw(Box::default());
+
+        match self.event.compare_exchange(
+            ptr::null_mut(),
+            new_event,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            // Safety: The event was just published, and is owned by `self`.
+            Ok(_) => unsafe { &*new_event },
+            Err(existing_event) => {
+                // Another thread created the event first, use theirs instead.
+                //
+                // Safety: The new event was never published, so this thread
+                // still owns it, while the existing event is owned by `self`.
+                unsafe {
+                    drop(Box::from_raw(new_event));
+                    &*existing_event
+                }
+            }
+        }
+    }
+}
+
+impl Default for LazyEvent {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for LazyEvent {
+    /// Frees the event if it was created.
+    fn drop(&mut self) {
+        let event = self.event.load(Ordering::Acquire);
+        if !event.is_null() {
+            // Safety: The event was allocated by `get_or_create`, and
+            // no references to it can outlive the `LazyEvent`.
+            drop(unsafe { Box::from_raw(event) });
+        }
+    }
+}
@@ -0,0 +1,83 @@
+// Copyright 2019 Brian Gianforcaro
+
+//! A global table of the callbacks which holders of run-down protection
+//! register to be woken when rundown is expedited, keyed by the address of
+//! the object, so neither the object nor its guards need space to hold them.
+
+use crate::{
+    sync::Mutex,
+    table::{address_table, Entries, Key},
+};
+use core::{fmt, marker::PhantomData};
+
+/// A callback registered to run when rundown is expedited.
+type Callback = Box<dyn FnOnce() + Send>;
+
+address_table! {
+    /// The callbacks registered on objects, indexed by the bucket of their address.
+    static REGISTRATIONS: Mutex<Entries<Callback>> = Mutex::new(Entries::new());
+}
+
+/// A callback registered to be invoked when rundown is expedited, which is
+/// unregistered when this structure is dropped (falls out of scope).
+///
+/// The registration borrows the guard it was registered on, so the callback
+/// is never invoked once the guard has released its protection.
+///
+/// This structure is created by the `on_expedite` method on `RundownGuard`
+/// and `OwnedRundownGuard`.
+#[must_use = "dropping the registration unregisters the callback"]
+pub struct ExpediteRegistration<'g> {
+    /// The key of the object in the table.
+    key: Key,
+
+    /// Identifies the registration among those in the bucket.
+    id: u64,
+
+    /// Ties the registration to the guard it was registered on.
+    _guard: PhantomData<&'g ()>,
+}
+
+impl Drop for ExpediteRegistration<'_> {
+    /// Unregisters the callback, unless it was already invoked.
+    fn drop(&mut self) {
+        let mut bucket = self.key.lock(&REGISTRATIONS);
+        let callback = bucket.remove(self.id);
+
+        // Drop the callback once the bucket is unlocked.
+        drop(bucket);
+        drop(callback);
+    }
+}
+
+impl fmt::Debug for ExpediteRegistration<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExpediteRegistration")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Registers `callback` to be invoked by `wake_holders` on `object`, until
+/// the returned registration is dropped.
+pub fn register<'g, T>(object: &T, callback: Callback) -> ExpediteRegistration<'g> {
+    let key = Key::of(object);
+    let id = key.lock(&REGISTRATIONS).insert(key, callback);
+
+    ExpediteRegistration {
+        key,
+        id,
+        _guard: PhantomData,
+    }
+}
+
+/// Invokes and unregisters all of the callbacks registered on `object`, in
+/// the order they were registered in.
+pub fn wake_holders<T>(object: &T) {
+    let key = Key::of(object);
+    let woken = key.lock(&REGISTRATIONS).take(key);
+
+    for callback in woken {
+        callback();
+    }
+}
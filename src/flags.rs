@@ -3,18 +3,112 @@
 use bitflags::bitflags;
 
 bitflags! {
+    /// The flags packed alongside the reference-count in a single u64.
+    ///
+    /// The flags are held in the most significant byte, one bit per flag,
+    /// and the reference-count in the remaining bits:
+    ///
+    /// ```text
+    ///   63    62    61    60 .. 56    55 ........ 0
+    /// +-----+-----+-----+----------+---------------+
+    /// |  P  |  C  |  K  | reserved | reference-cnt |
+    /// +-----+-----+-----+----------+---------------+
+    /// ```
+    ///
+    /// - `P`: Rundown is in progress, no new references can be acquired.
+    /// - `C`: Rundown is complete, all references have been released.
+    /// - `K`: Callbacks are registered to run once rundown completes.
+    /// - `reserved`: Always zero, set aside for future flags.
     pub struct RundownFlags: u64 {
-        const RUNDOWN_IN_PROGRESS = 0xF000_0000_0000_0000;
+        const RUNDOWN_IN_PROGRESS = 1 << 63;
+        const RUNDOWN_COMPLETE    = 1 << 62;
+        const RUNDOWN_CALLBACKS   = 1 << 61;
     }
 }
 
+/// The states a run-down reference moves through.
+///
+/// A reference starts out `Active`, moves to `RundownInProgress` once
+/// rundown starts, to `RundownComplete` once all outstanding references
+/// have been released, and back to `Active` when it is re-initialized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RundownState {
+    /// Run-down protection can be acquired.
+    Active,
+
+    /// Rundown has started, but there are still outstanding references.
+    RundownInProgress,
+
+    /// Rundown has completed, there are no outstanding references.
+    RundownComplete,
+}
+
 impl RundownFlags {
+    /// The bits holding the reference-count.
+    pub const REF_COUNT_MASK: u64 = (1 << 56) - 1;
+
+    /// The largest reference-count which can be acquired. The most significant
+    /// bit of the reference-count is headroom for acquisitions which briefly
+    /// take the count past the maximum, before they back out again.
+    pub const MAX_REF_COUNT: u64 = Self::REF_COUNT_MASK >> 1;
+
+    /// Returns the state encoded in the flags.
+    #[inline]
+    pub const fn state(self) -> RundownState {
+        if self.is_pre_rundown() {
+            RundownState::Active
+        } else if self.is_rundown_complete() {
+            RundownState::RundownComplete
+        } else {
+            RundownState::RundownInProgress
+        }
+    }
+
+    /// Returns true if the run-down complete flag is set.
+    #[inline]
+    pub const fn is_rundown_complete(self) -> bool {
+        self.contains(Self::RUNDOWN_COMPLETE)
+    }
+
+    /// Returns a new reference-count with the run-down
+    /// complete flag set in the upper bits.
+    #[inline]
+    pub const fn set_rundown_complete(self) -> u64 {
+        self.bits | Self::RUNDOWN_COMPLETE.bits
+    }
+
+    /// Returns a new reference-count with the run-down in progress flag set,
+    /// and the run-down complete flag as well if there are no references left.
+    #[inline]
+    pub const fn start_rundown(self) -> u64 {
+        let bits_with_rundown = self.set_rundown_in_progress();
+        if self.is_ref_zero() {
+            to_flags(bits_with_rundown).set_rundown_complete()
+        } else {
+            bits_with_rundown
+        }
+    }
+
     /// Returns true if the run-down in progress flag is set.
     #[inline]
     pub const fn is_rundown_in_progress(self) -> bool {
         self.contains(Self::RUNDOWN_IN_PROGRESS)
     }
 
+    /// Returns true if the callbacks registered flag is set.
+    #[inline]
+    pub const fn has_callbacks(self) -> bool {
+        self.contains(Self::RUNDOWN_CALLBACKS)
+    }
+
+    /// Returns a new reference-count with the callbacks
+    /// registered flag set in the upper bits.
+    #[inline]
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub const fn set_callbacks(self) -> u64 {
+        self.bits | Self::RUNDOWN_CALLBACKS.bits
+    }
+
     /// Returns true if the run-down in progress flag is not set.
     #[inline]
     pub const fn is_pre_rundown(self) -> bool {
@@ -31,7 +125,7 @@ impl RundownFlags {
     /// Returns just the reference-count encoded in the flags.
     #[inline]
     pub const fn get_ref(self) -> u64 {
-        self.bits & (!Self::RUNDOWN_IN_PROGRESS.bits)
+        self.bits & Self::REF_COUNT_MASK
     }
 
     /// Returns true if the reference-count is zero.
@@ -42,27 +136,38 @@ impl RundownFlags {
 
     /// Returns true if the reference-count is non zero.
     #[inline]
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
     pub const fn is_ref_active(self) -> bool {
         self.get_ref() > 0
     }
 
-    /// Returns a new reference-count with a incremented reference count.
+    /// Returns a new reference-count with `count` references added, or `None`
+    /// if the reference-count would exceed `MAX_REF_COUNT`.
     #[inline]
-    pub fn add_ref(self) -> u64 {
-        if let Some(new_value) = self.bits.checked_add(1) {
-            new_value
-        } else {
-            panic!("Incrementing the reference-count would have over-flowed!");
+    pub const fn add_refs(self, count: u64) -> Option<u64> {
+        match self.get_ref().checked_add(count) {
+            Some(new_count) if new_count <= Self::MAX_REF_COUNT => Some(self.bits + count),
+            _ => None,
         }
     }
 
-    /// Returns a new reference-count with a decremented reference count.
+    /// Returns a new reference-count with `count` references removed.
     #[inline]
-    pub fn dec_ref(self) -> u64 {
-        if let Some(new_value) = self.bits.checked_sub(1) {
-            new_value
+    pub const fn dec_refs(self, count: u64) -> u64 {
+        match self.checked_dec_refs(count) {
+            Some(bits) => bits,
+            None => panic!("Decrementing the reference-count would have under-flowed!"),
+        }
+    }
+
+    /// Returns a new reference-count with `count` references removed, or
+    /// `None` if fewer than `count` references are outstanding.
+    #[inline]
+    pub const fn checked_dec_refs(self, count: u64) -> Option<u64> {
+        if self.get_ref() >= count {
+            Some(self.bits - count)
         } else {
-            panic!("Decrementing the reference-count would have under-flowed!");
+            None
         }
     }
 }
@@ -78,7 +183,7 @@ pub const fn to_flags(bits: u64) -> RundownFlags {
 
 #[cfg(test)]
 mod test {
-    use super::{to_flags, RundownFlags};
+    use super::{to_flags, RundownFlags, RundownState};
     use pretty_assertions::assert_eq;
 
     //-------------------------------------------------------------------
@@ -97,13 +202,13 @@ mod test {
         assert_eq!(false, flags.is_ref_active());
 
         // Validate that add ref works.
-        flags = to_flags(flags.add_ref());
+        flags = to_flags(flags.add_refs(1).unwrap());
         assert_eq!(1, flags.get_ref());
         assert_eq!(false, flags.is_ref_zero());
         assert_eq!(true, flags.is_ref_active());
 
         // Validate that dec ref works.
-        flags = to_flags(flags.dec_ref());
+        flags = to_flags(flags.dec_refs(1));
         assert_eq!(0, flags.get_ref());
         assert_eq!(true, flags.is_ref_zero());
         assert_eq!(false, flags.is_ref_active());
@@ -136,23 +241,23 @@ mod test {
         assert_eq!(false, flags.is_pre_rundown());
 
         // Incrementing the reference count should work, and preserve flags.
-        flags = to_flags(flags.add_ref());
+        flags = to_flags(flags.add_refs(1).unwrap());
         assert_eq!(1, flags.get_ref());
         assert_eq!(true, flags.is_rundown_in_progress());
         assert_eq!(false, flags.is_pre_rundown());
     }
 
     //-------------------------------------------------------------------
-    // Test: test_rundown_flags_overflow_panic
+    // Test: test_rundown_flags_overflow
     //
     // Description:
-    //  A test case to validate that reference-count panics on overflow.
+    //  A test case to validate that the reference-count doesn't overflow
+    //  into the flag bits.
     //
     #[test]
-    #[should_panic]
-    fn test_rundown_flags_overflow_panic() {
+    fn test_rundown_flags_overflow() {
         let flags = to_flags(0xFFFF_FFFF_FFFF_FFFF);
-        flags.add_ref();
+        assert_eq!(None, flags.add_refs(1));
     }
 
     //-------------------------------------------------------------------
@@ -162,22 +267,22 @@ mod test {
     //  A test case to validate that reference-count panics on underflow.
     //
     #[test]
-    #[should_panic]
+    #[should_panic(expected = "under-flowed")]
     fn test_rundown_flags_underflow_panic() {
         let flags = RundownFlags::empty();
-        flags.dec_ref();
+        flags.dec_refs(1);
     }
 
     //-------------------------------------------------------------------
-    // Test: test_add_ref
+    // Test: test_add_refs
     //
     // Description:
-    //  A test case to validate that `add_ref` works as expected.
+    //  A test case to validate that `add_refs` works as expected.
     //
     #[test]
-    fn test_add_ref() {
+    fn test_add_refs() {
         let mut flags = RundownFlags::empty();
-        flags = to_flags(flags.add_ref());
+        flags = to_flags(flags.add_refs(1).unwrap());
 
         assert_eq!(1, flags.get_ref());
         assert!(!flags.is_ref_zero());
@@ -185,15 +290,15 @@ mod test {
     }
 
     //-------------------------------------------------------------------
-    // Test: test_dec_ref
+    // Test: test_dec_refs
     //
     // Description:
-    //  A test case to validate that `dec_ref` works as expected.
+    //  A test case to validate that `dec_refs` works as expected.
     //
     #[test]
-    fn test_def_ref() {
+    fn test_dec_refs() {
         let mut flags = to_flags(0x1);
-        flags = to_flags(flags.dec_ref());
+        flags = to_flags(flags.dec_refs(1));
 
         assert_eq!(0, flags.get_ref());
         assert!(flags.is_ref_zero());
@@ -209,8 +314,155 @@ mod test {
     //
     #[test]
     fn test_to_flags() {
-        let flags = to_flags(0xF000_0000_0000_0001);
+        let flags = to_flags(0x8000_0000_0000_0001);
+        assert_eq!(1, flags.get_ref());
+        assert_eq!(true, flags.is_rundown_in_progress());
+    }
+
+    //-------------------------------------------------------------------
+    // Test: test_rundown_flags_layout
+    //
+    // Description:
+    //  A test case to validate that each flag is a single bit, that the
+    //  flags don't overlap the reference-count, and the documented limits.
+    //
+    #[test]
+    fn test_rundown_flags_layout() {
+        assert_eq!(1, RundownFlags::RUNDOWN_IN_PROGRESS.bits().count_ones());
+        assert_eq!(1, RundownFlags::RUNDOWN_COMPLETE.bits().count_ones());
+        assert_eq!(1, RundownFlags::RUNDOWN_CALLBACKS.bits().count_ones());
+        assert_eq!(0, RundownFlags::all().bits() & RundownFlags::REF_COUNT_MASK);
+        assert_eq!(0xFF00_0000_0000_0000, !RundownFlags::REF_COUNT_MASK);
+        assert_eq!((1 << 55) - 1, RundownFlags::MAX_REF_COUNT);
+    }
+
+    //-------------------------------------------------------------------
+    // Test: test_add_refs_limit
+    //
+    // Description:
+    //  A test case to validate that adding references up to the maximum
+    //  succeeds, and going past it fails without touching the flags.
+    //
+    #[test]
+    fn test_add_refs_limit() {
+        let flags = to_flags(RundownFlags::MAX_REF_COUNT - 1);
+        assert_eq!(Some(RundownFlags::MAX_REF_COUNT), flags.add_refs(1));
+        assert_eq!(None, flags.add_refs(2));
+
+        let flags = to_flags(RundownFlags::MAX_REF_COUNT);
+        assert_eq!(None, flags.add_refs(1));
+        assert_eq!(None, flags.add_refs(u64::MAX));
+
+        // The limit applies to the count, regardless of the flags.
+        let flags = to_flags(RundownFlags::MAX_REF_COUNT - 1);
+        let flags = to_flags(flags.set_rundown_in_progress());
+        assert_eq!(None, flags.add_refs(2));
+    }
+
+    //-------------------------------------------------------------------
+    // Test: test_rundown_state_transitions
+    //
+    // Description:
+    //  A test case to validate the state transitions of the flags,
+    //  from active, through rundown in progress, to rundown complete.
+    //
+    #[test]
+    fn test_rundown_state_transitions() {
+        let mut flags = RundownFlags::empty();
+        assert_eq!(RundownState::Active, flags.state());
+
+        // Starting rundown with an outstanding reference.
+        flags = to_flags(flags.add_refs(1).unwrap());
+        flags = to_flags(flags.start_rundown());
+        assert_eq!(RundownState::RundownInProgress, flags.state());
         assert_eq!(1, flags.get_ref());
+
+        // Releasing the last reference leaves rundown in progress,
+        // until the complete flag is set.
+        flags = to_flags(flags.dec_refs(1));
+        assert_eq!(RundownState::RundownInProgress, flags.state());
+        flags = to_flags(flags.set_rundown_complete());
+        assert_eq!(RundownState::RundownComplete, flags.state());
+        assert_eq!(0, flags.get_ref());
         assert_eq!(true, flags.is_rundown_in_progress());
     }
+
+    //-------------------------------------------------------------------
+    // Test: test_start_rundown_without_refs
+    //
+    // Description:
+    //  A test case to validate that starting rundown without any
+    //  outstanding references moves straight to rundown complete,
+    //  and that releasing before rundown leaves the object active.
+    //
+    #[test]
+    fn test_start_rundown_without_refs() {
+        let flags = to_flags(RundownFlags::empty().start_rundown());
+        assert_eq!(RundownState::RundownComplete, flags.state());
+
+        let flags = to_flags(to_flags(0x1).dec_refs(1));
+        assert_eq!(RundownState::Active, flags.state());
+    }
+
+    //-------------------------------------------------------------------
+    // Test: test_add_and_dec_refs
+    //
+    // Description:
+    //  A test case to validate that adding and removing multiple
+    //  references at once works, and preserves the flags.
+    //
+    #[test]
+    fn test_add_and_dec_refs() {
+        let mut flags = to_flags(RundownFlags::empty().add_refs(10).unwrap());
+        assert_eq!(10, flags.get_ref());
+
+        flags = to_flags(flags.set_rundown_in_progress());
+        flags = to_flags(flags.dec_refs(4));
+        assert_eq!(6, flags.get_ref());
+        assert_eq!(RundownState::RundownInProgress, flags.state());
+
+        flags = to_flags(flags.dec_refs(6));
+        assert_eq!(0, flags.get_ref());
+        assert_eq!(RundownState::RundownInProgress, flags.state());
+    }
+
+    //-------------------------------------------------------------------
+    // Test: test_callbacks_flag
+    //
+    // Description:
+    //  A test case to validate that the callbacks registered flag
+    //  doesn't affect the state or the reference-count, and that it
+    //  is preserved when rundown completes.
+    //
+    #[test]
+    fn test_callbacks_flag() {
+        let mut flags = to_flags(to_flags(0x1).start_rundown());
+        assert_eq!(false, flags.has_callbacks());
+
+        flags = to_flags(flags.set_callbacks());
+        assert_eq!(true, flags.has_callbacks());
+        assert_eq!(RundownState::RundownInProgress, flags.state());
+        assert_eq!(1, flags.get_ref());
+
+        flags = to_flags(flags.dec_refs(1));
+        flags = to_flags(flags.set_rundown_complete());
+        assert_eq!(true, flags.has_callbacks());
+        assert_eq!(RundownState::RundownComplete, flags.state());
+        assert_eq!(0, flags.get_ref());
+    }
+
+    //-------------------------------------------------------------------
+    // Test: test_dec_refs_underflow_panic
+    //
+    // Description:
+    //  A test case to validate that removing multiple references panics
+    //  if there are fewer references than are being removed, even if
+    //  the flag bits are set.
+    //
+    #[test]
+    #[should_panic(expected = "under-flowed")]
+    fn test_dec_refs_underflow_panic() {
+        let flags = to_flags(RundownFlags::empty().set_rundown_in_progress());
+        flags.dec_refs(1);
+    }
 }
@@ -0,0 +1,232 @@
+// Copyright 2019 Brian Gianforcaro
+
+use crate::flags::RundownState;
+use bitflags::bitflags;
+
+bitflags! {
+    /// The 32-bit counterpart of `RundownFlags`, used by `RundownRef32`.
+    ///
+    /// The flags are held in the two most significant bits, one bit per
+    /// flag, and the reference-count in the remaining bits:
+    ///
+    /// ```text
+    ///   31    30    29 ......... 0
+    /// +-----+-----+---------------+
+    /// |  P  |  C  | reference-cnt |
+    /// +-----+-----+---------------+
+    /// ```
+    pub struct RundownFlags32: u32 {
+        const RUNDOWN_IN_PROGRESS = 1 << 31;
+        const RUNDOWN_COMPLETE    = 1 << 30;
+    }
+}
+
+impl RundownFlags32 {
+    /// The bits holding the reference-count.
+    pub const REF_COUNT_MASK: u32 = (1 << 30) - 1;
+
+    /// The largest reference-count which can be acquired. The most significant
+    /// bit of the reference-count is headroom for acquisitions which briefly
+    /// take the count past the maximum, before they back out again.
+    pub const MAX_REF_COUNT: u32 = Self::REF_COUNT_MASK >> 1;
+
+    /// Returns the state encoded in the flags.
+    #[inline]
+    pub const fn state(self) -> RundownState {
+        if self.is_pre_rundown() {
+            RundownState::Active
+        } else if self.is_rundown_complete() {
+            RundownState::RundownComplete
+        } else {
+            RundownState::RundownInProgress
+        }
+    }
+
+    /// Returns true if the run-down complete flag is set.
+    #[inline]
+    pub const fn is_rundown_complete(self) -> bool {
+        self.contains(Self::RUNDOWN_COMPLETE)
+    }
+
+    /// Returns a new reference-count with the run-down
+    /// complete flag set in the upper bits.
+    #[inline]
+    pub const fn set_rundown_complete(self) -> u32 {
+        self.bits | Self::RUNDOWN_COMPLETE.bits
+    }
+
+    /// Returns a new reference-count with the run-down in progress flag set,
+    /// and the run-down complete flag as well if there are no references left.
+    #[inline]
+    pub const fn start_rundown(self) -> u32 {
+        let bits_with_rundown = self.bits | Self::RUNDOWN_IN_PROGRESS.bits;
+        if self.is_ref_zero() {
+            to_flags32(bits_with_rundown).set_rundown_complete()
+        } else {
+            bits_with_rundown
+        }
+    }
+
+    /// Returns true if the run-down in progress flag is set.
+    #[inline]
+    pub const fn is_rundown_in_progress(self) -> bool {
+        self.contains(Self::RUNDOWN_IN_PROGRESS)
+    }
+
+    /// Returns true if the run-down in progress flag is not set.
+    #[inline]
+    pub const fn is_pre_rundown(self) -> bool {
+        !self.contains(Self::RUNDOWN_IN_PROGRESS)
+    }
+
+    /// Returns just the reference-count encoded in the flags.
+    #[inline]
+    pub const fn get_ref(self) -> u32 {
+        self.bits & Self::REF_COUNT_MASK
+    }
+
+    /// Returns true if the reference-count is zero.
+    #[inline]
+    pub const fn is_ref_zero(self) -> bool {
+        self.get_ref() == 0
+    }
+
+    /// Returns true if the reference-count is non zero.
+    #[inline]
+    pub const fn is_ref_active(self) -> bool {
+        self.get_ref() > 0
+    }
+
+    /// Returns a new reference-count with `count` references added.
+    #[cfg(test)]
+    pub const fn add_refs(self, count: u32) -> u32 {
+        match self.checked_add_refs(count) {
+            Some(bits) => bits,
+            None => panic!("Incrementing the reference-count would have over-flowed!"),
+        }
+    }
+
+    /// Returns a new reference-count with `count` references added, or `None`
+    /// if the reference-count would exceed `MAX_REF_COUNT`.
+    #[inline]
+    pub const fn checked_add_refs(self, count: u32) -> Option<u32> {
+        match self.get_ref().checked_add(count) {
+            Some(new_count) if new_count <= Self::MAX_REF_COUNT => Some(self.bits + count),
+            _ => None,
+        }
+    }
+
+    /// Returns a new reference-count with `count` references removed.
+    #[inline]
+    pub const fn dec_refs(self, count: u32) -> u32 {
+        match self.checked_dec_refs(count) {
+            Some(bits) => bits,
+            None => panic!("Decrementing the reference-count would have under-flowed!"),
+        }
+    }
+
+    /// Returns a new reference-count with `count` references removed, or
+    /// `None` if fewer than `count` references are outstanding.
+    #[inline]
+    pub const fn checked_dec_refs(self, count: u32) -> Option<u32> {
+        if self.get_ref() >= count {
+            Some(self.bits - count)
+        } else {
+            None
+        }
+    }
+}
+
+/// Utility function for converting raw bits to `RundownFlags32`.
+#[inline]
+pub const fn to_flags32(bits: u32) -> RundownFlags32 {
+    // To preserve the reference-count bits which are encoded with
+    // the flags we need to use the unchecked version. This requires
+    // the use of unsafe.
+    unsafe { RundownFlags32::from_bits_unchecked(bits) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{to_flags32, RundownFlags32};
+    use crate::flags::RundownState;
+    use pretty_assertions::assert_eq;
+
+    //-------------------------------------------------------------------
+    // Test: test_rundown_flags32_layout
+    //
+    // Description:
+    //  A test case to validate that the flags and the reference-count
+    //  partition the 32-bit word without overlapping.
+    //
+    #[test]
+    fn test_rundown_flags32_layout() {
+        let flags = RundownFlags32::all();
+        assert_eq!(0, flags.bits() & RundownFlags32::REF_COUNT_MASK);
+        assert_eq!(u32::MAX, flags.bits() | RundownFlags32::REF_COUNT_MASK);
+        assert_eq!((1 << 29) - 1, RundownFlags32::MAX_REF_COUNT);
+        assert_eq!(
+            None,
+            to_flags32(RundownFlags32::MAX_REF_COUNT).checked_add_refs(1)
+        );
+    }
+
+    //-------------------------------------------------------------------
+    // Test: test_rundown_flags32_state_transitions
+    //
+    // Description:
+    //  A test case to validate the states encoded by the flags as a
+    //  reference is acquired, rundown starts and the reference released.
+    //
+    #[test]
+    fn test_rundown_flags32_state_transitions() {
+        let mut flags = RundownFlags32::empty();
+        assert_eq!(RundownState::Active, flags.state());
+
+        flags = to_flags32(flags.add_refs(1));
+        assert_eq!(1, flags.get_ref());
+
+        flags = to_flags32(flags.start_rundown());
+        assert_eq!(RundownState::RundownInProgress, flags.state());
+        assert_eq!(1, flags.get_ref());
+
+        flags = to_flags32(flags.dec_refs(1));
+        assert_eq!(RundownState::RundownInProgress, flags.state());
+        assert_eq!(true, flags.is_ref_zero());
+
+        flags = to_flags32(flags.set_rundown_complete());
+        assert_eq!(RundownState::RundownComplete, flags.state());
+
+        // Starting rundown without references completes it immediately.
+        flags = to_flags32(RundownFlags32::empty().start_rundown());
+        assert_eq!(RundownState::RundownComplete, flags.state());
+    }
+
+    //-------------------------------------------------------------------
+    // Test: test_add_refs32_overflow_panic
+    //
+    // Description:
+    //  A test case to validate that adding references panics when the
+    //  count would run into the flag bits.
+    //
+    #[test]
+    #[should_panic(expected = "over-flowed")]
+    fn test_add_refs32_overflow_panic() {
+        let flags = to_flags32(RundownFlags32::MAX_REF_COUNT);
+        flags.add_refs(1);
+    }
+
+    //-------------------------------------------------------------------
+    // Test: test_dec_refs32_underflow_panic
+    //
+    // Description:
+    //  A test case to validate that removing references panics when
+    //  fewer references are outstanding.
+    //
+    #[test]
+    #[should_panic(expected = "under-flowed")]
+    fn test_dec_refs32_underflow_panic() {
+        let flags = to_flags32(RundownFlags32::RUNDOWN_IN_PROGRESS.bits() | 1);
+        flags.dec_refs(2);
+    }
+}
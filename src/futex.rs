@@ -0,0 +1,261 @@
+// Copyright 2019 Brian Gianforcaro
+
+//! Waiting for rundown through a futex on the reference count word itself,
+//! which removes the need for a per object event on Linux.
+//!
+//! A futex operates on 32-bit words, so threads wait on the most significant
+//! half of a 64-bit word, which holds the flags, or on the whole of a 32-bit
+//! word. Completing rundown always changes the flags, so the thread which
+//! completes rundown wakes every waiter, and a thread which is about to wait
+//! observes the change instead of blocking.
+//!
+//! Should the object complete rundown, be re-initialized and start rundown
+//! again between a thread reading the word and blocking, the flags read the
+//! same again, and the thread returns once that next rundown completes.
+
+#[cfg(feature = "async")]
+use crate::{
+    sync::Mutex,
+    table::{address_table, Entries, Key},
+};
+#[cfg(feature = "async")]
+use std::task::Waker;
+use std::{
+    convert::TryFrom,
+    ptr,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// A reference count word which threads can wait on with a futex.
+pub trait FutexWord {
+    /// The value held by the word.
+    type Value: Copy;
+
+    /// Atomically loads the value of the word.
+    fn load_value(&self) -> Self::Value;
+
+    /// Returns the part of `value` held by the 32-bit word the futex waits on.
+    fn futex_value(value: Self::Value) -> u32;
+
+    /// Returns a pointer to the 32-bit word the futex waits on.
+    fn futex_ptr(&self) -> *const u32;
+}
+
+impl FutexWord for AtomicU64 {
+    type Value = u64;
+
+    #[inline]
+    fn load_value(&self) -> u64 {
+        self.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    fn futex_value(value: u64) -> u32 {
+        high_half(value)
+    }
+
+    #[inline]
+    fn futex_ptr(&self) -> *const u32 {
+        high_half_ptr(self)
+    }
+}
+
+impl FutexWord for AtomicU32 {
+    type Value = u32;
+
+    #[inline]
+    fn load_value(&self) -> u32 {
+        self.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    fn futex_value(value: u32) -> u32 {
+        value
+    }
+
+    #[inline]
+    fn futex_ptr(&self) -> *const u32 {
+        self.as_ptr().cast_const()
+    }
+}
+
+/// Blocks thread execution while `condition` returns true for the value of
+/// `word`, for at most `timeout` if one is provided. Returns true if the wait
+/// ended because the condition no longer held, or false if the timeout
+/// elapsed first.
+///
+/// The condition must only change from true to false when the part of `word`
+/// the futex waits on changes, and `wake_all` is called afterwards.
+pub fn wait_while<W, F>(word: &W, timeout: Option<Duration>, condition: F) -> bool
+where
+    W: FutexWord,
+    F: Fn(W::Value) -> bool,
+{
+    let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+
+    loop {
+        let current = word.load_value();
+
+        if !condition(current) {
+            return true;
+        }
+
+        let remaining = match timeout {
+            Some(timeout) => {
+                let remaining = deadline.map_or(timeout, |deadline| {
+                    deadline.saturating_duration_since(Instant::now())
+                });
+
+                if remaining.is_zero() {
+                    return false;
+                }
+
+                Some(remaining)
+            }
+            None => None,
+        };
+
+        // Spurious wake-ups, interruptions and a changed value all
+        // just result in the condition being evaluated again.
+        futex_wait(word.futex_ptr(), W::futex_value(current), remaining);
+    }
+}
+
+/// Wakes all threads blocked in `wait_while` on `word`.
+pub fn wake_all<W: FutexWord>(word: &W) {
+    // SAFETY: The address refers to a live atomic for the duration of the call.
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            word.futex_ptr(),
+            libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG,
+            libc::c_int::MAX,
+        );
+    }
+}
+
+/// Blocks the thread until woken, if the 32-bit word at `address` still
+/// holds `expected`.
+fn futex_wait(address: *const u32, expected: u32, timeout: Option<Duration>) {
+    // The width of the timespec fields depends on the target.
+    #[allow(clippy::unnecessary_fallible_conversions)]
+    let timespec = timeout.map(|timeout| libc::timespec {
+        tv_sec: libc::time_t::try_from(timeout.as_secs()).unwrap_or(libc::time_t::MAX),
+        tv_nsec: libc::c_long::try_from(timeout.subsec_nanos()).unwrap_or_default(),
+    });
+
+    let timespec_ptr = timespec.as_ref().map_or(ptr::null(), ptr::from_ref);
+
+    // SAFETY: The address refers to a live atomic for the duration of the
+    // call, and the timeout is either null or points to a live timespec.
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            address,
+            libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+            expected,
+            timespec_ptr,
+        );
+    }
+}
+
+/// Returns the most significant half of `bits`.
+#[allow(clippy::cast_possible_truncation)]
+const fn high_half(bits: u64) -> u32 {
+    (bits >> 32) as u32
+}
+
+/// Returns a pointer to the most significant half of `word`.
+const fn high_half_ptr(word: &AtomicU64) -> *const u32 {
+    let ptr = word.as_ptr().cast::<u32>().cast_const();
+
+    if cfg!(target_endian = "little") {
+        ptr.wrapping_add(1)
+    } else {
+        ptr
+    }
+}
+
+#[cfg(feature = "async")]
+address_table! {
+    /// The wakers of the tasks awaiting rundown, keyed by the address of the
+    /// word. The futex can only block threads, so tasks are parked in a global
+    /// table instead of a per object event, keeping the object the size of the
+    /// word.
+    static TASKS: Mutex<Entries<Waker>> = Mutex::new(Entries::new());
+}
+
+/// Registers `waker` to be woken by `wake_tasks` on `word`, if `condition`
+/// returns true. Returns the result of the condition.
+///
+/// The registration of the task is kept in `task`, so polling again only
+/// updates its waker, and `unregister` can remove it once the task stops
+/// waiting.
+///
+/// The condition is evaluated with the bucket locked, and `wake_tasks`
+/// locks the bucket after the condition changes, so the wake-up can't be
+/// missed.
+#[cfg(feature = "async")]
+pub fn register_while<F>(
+    word: &AtomicU64,
+    task: &mut Option<u64>,
+    waker: &Waker,
+    condition: F,
+) -> bool
+where
+    F: FnOnce() -> bool,
+{
+    let key = Key::of(word);
+    let mut bucket = key.lock(&TASKS);
+
+    if !condition() {
+        return false;
+    }
+
+    // The registration is gone once the task was woken.
+    match task.and_then(|id| bucket.get_mut(id)) {
+        Some(registered) => {
+            if !registered.will_wake(waker) {
+                registered.clone_from(waker);
+            }
+        }
+        None => *task = Some(bucket.insert(key, waker.clone())),
+    }
+
+    drop(bucket);
+    true
+}
+
+/// Removes the registration `task` on `word`, unless the task was woken.
+#[cfg(feature = "async")]
+pub fn unregister(word: &AtomicU64, task: u64) {
+    let mut bucket = Key::of(word).lock(&TASKS);
+    let waker = bucket.remove(task);
+
+    // Drop the waker once the bucket is unlocked.
+    drop(bucket);
+    drop(waker);
+}
+
+/// Wakes all tasks registered with `register_while` on `word`.
+#[cfg(feature = "async")]
+pub fn wake_tasks(word: &AtomicU64) {
+    for waker in take_tasks(word) {
+        waker.wake();
+    }
+}
+
+/// Removes all tasks registered on `word` without waking them, so they
+/// can't be woken once another word at the same address is run-down.
+#[cfg(feature = "async")]
+pub fn discard_tasks(word: &AtomicU64) {
+    drop(take_tasks(word));
+}
+
+/// Removes and returns the wakers of all tasks registered on `word`.
+#[cfg(feature = "async")]
+fn take_tasks(word: &AtomicU64) -> Vec<Waker> {
+    let key = Key::of(word);
+    key.lock(&TASKS).take(key)
+}
@@ -0,0 +1,107 @@
+// Copyright 2019 Brian Gianforcaro
+
+use crate::{cache_aware::CacheAwareRundownRef, rundown_ref::RundownRef};
+use std::{future::Future, pin::Pin, task::Context, task::Poll};
+
+/// A future which completes once all outstanding run-down protection
+/// on a [`RundownRef`] has been released.
+///
+/// This structure is created by the `wait_for_rundown_async` method on `RundownRef`.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct RundownFuture<'r> {
+    /// The run-down reference that this future is waiting on.
+    rundown_ref: &'r RundownRef,
+
+    /// The registration of the waker of the task awaiting the future,
+    /// once the future has been polled while rundown was pending.
+    task: Option<u64>,
+}
+
+impl<'r> RundownFuture<'r> {
+    /// Creates a new [`RundownFuture`] which waits for rundown
+    /// of the [`RundownRef`] provided.
+    pub(crate) const fn new(rundown_ref: &'r RundownRef) -> Self {
+        Self {
+            rundown_ref,
+            task: None,
+        }
+    }
+}
+
+impl Future for RundownFuture<'_> {
+    type Output = ();
+
+    /// Marks the run-down reference as 'rundown in progress', and
+    /// completes once there are no outstanding references on it.
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+
+        if this.rundown_ref.poll_rundown(&mut this.task, cx.waker()) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for RundownFuture<'_> {
+    /// Removes the waker of a future which is dropped before rundown
+    /// completes, such as when a timeout or `select!` gives up on it.
+    fn drop(&mut self) {
+        if let Some(task) = self.task {
+            self.rundown_ref.cancel_poll(task);
+        }
+    }
+}
+
+/// A future which completes once all outstanding run-down protection on
+/// every shard of a [`CacheAwareRundownRef`] has been released, like
+/// [`RundownFuture`].
+///
+/// This structure is created by the `wait_for_rundown_async` method on
+/// `CacheAwareRundownRef`.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct CacheAwareRundownFuture<'r> {
+    /// The run-down reference that this future is waiting on.
+    rundown_ref: &'r CacheAwareRundownRef,
+
+    /// The registration of the waker of the task awaiting the future,
+    /// once the future has been polled while rundown was pending.
+    task: Option<u64>,
+}
+
+impl<'r> CacheAwareRundownFuture<'r> {
+    /// Creates a new [`CacheAwareRundownFuture`] which waits for rundown
+    /// of the [`CacheAwareRundownRef`] provided.
+    pub(crate) const fn new(rundown_ref: &'r CacheAwareRundownRef) -> Self {
+        Self {
+            rundown_ref,
+            task: None,
+        }
+    }
+}
+
+impl Future for CacheAwareRundownFuture<'_> {
+    type Output = ();
+
+    /// Marks every shard of the run-down reference as 'rundown in progress',
+    /// and completes once there are no outstanding references on them.
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+
+        if this.rundown_ref.poll_rundown(&mut this.task, cx.waker()) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for CacheAwareRundownFuture<'_> {
+    /// Removes the waker of a future which is dropped before rundown completes.
+    fn drop(&mut self) {
+        if let Some(task) = self.task {
+            self.rundown_ref.cancel_poll(task);
+        }
+    }
+}
@@ -1,38 +1,511 @@
 // Copyright 2019 Brian Gianforcaro
 
-use crate::rundown_ref::RundownRef;
+#[cfg(feature = "track-holders")]
+use crate::holders::HolderId;
+#[cfg(feature = "std")]
+use crate::{cache_aware::CacheAwareRundownRef, expedite, expedite::ExpediteRegistration};
+use crate::{
+    rundown_ref::RundownRef, rundown_ref32::RundownRef32, wait::DefaultWaitStrategy,
+    wait::WaitStrategy,
+};
+use core::fmt;
+#[cfg(feature = "std")]
+use std::sync::Arc;
 
 /// An RAII implementation of a "scoped lock" pattern, but specialized
-/// to the needs of run-down protection. When this structure is dropped
-/// (falls out of scope), the rundown protection reference that was
-/// previously acquired is released.
+/// to the needs of run-down protection.
+///
+/// When this structure is dropped (falls out of scope), the rundown
+/// protection reference that was previously acquired is released.
 ///
 /// This structure is created by the `try_acquire` method on `RundownRef`.
 ///
 /// This type attempts to follow the RAII guidance here:
 /// <https://github.com/rust-unofficial/patterns/blob/master/patterns/RAII.md>
-pub struct RundownGuard<'r> {
+pub struct RundownGuard<'r, W: WaitStrategy = DefaultWaitStrategy> {
     /// The run-dwon reference that this guard objec points too.
-    owned_run_down_ref: &'r RundownRef,
+    owned_run_down_ref: &'r RundownRef<W>,
+
+    /// The number of instances of run-down protection owned by this guard.
+    count: u64,
+
+    /// True if the guard was created by hand with `new`, so it isn't known
+    /// to hold the protection it releases.
+    by_hand: bool,
+
+    /// The entry of the guard in the table of holders, unless the
+    /// guard was created by hand.
+    #[cfg(feature = "track-holders")]
+    holder: Option<HolderId>,
 }
 
-impl<'r> RundownGuard<'r> {
+impl<'r, W: WaitStrategy> RundownGuard<'r, W> {
     /// Creates a new [`RundownGuard`] which owns an instance of run-down
     /// protection on the [`RundownRef`] provided.
     ///
+    /// The guard releases the protection with `RundownRef::release`, so
+    /// dropping it panics, leaving the reference count unchanged, if no
+    /// references are outstanding.
+    ///
+    /// # Arguments
+    ///
+    /// * `owned_run_down_ref` - The run-down reference to release when the
+    ///   guard goes out of scope.
+    ///
+    #[must_use]
+    pub const fn new(owned_run_down_ref: &'r RundownRef<W>) -> Self {
+        Self {
+            owned_run_down_ref,
+            count: 1,
+            by_hand: true,
+            #[cfg(feature = "track-holders")]
+            holder: None,
+        }
+    }
+
+    /// Creates a new [`RundownGuard`] which owns `count` instances of
+    /// run-down protection on the [`RundownRef`] provided.
+    ///
     /// # Arguments
     ///
     /// * `owned_run_down_ref` - The run-down reference to release when the
-    ///                          guard goes out of scope.
+    ///   guard goes out of scope.
     ///
-    pub const fn new(owned_run_down_ref: &'r RundownRef) -> RundownGuard<'r> {
-        Self { owned_run_down_ref }
+    /// * `count` - The number of instances of protection owned by the guard.
+    ///
+    #[cfg_attr(feature = "track-holders", allow(dead_code))]
+    pub(crate) const fn with_count(owned_run_down_ref: &'r RundownRef<W>, count: u64) -> Self {
+        Self {
+            owned_run_down_ref,
+            count,
+            by_hand: false,
+            #[cfg(feature = "track-holders")]
+            holder: None,
+        }
+    }
+
+    /// Creates a new [`RundownGuard`] which owns `count` instances of
+    /// run-down protection, recorded in the table of holders as `holder`.
+    #[cfg(feature = "track-holders")]
+    pub(crate) const fn tracked(
+        owned_run_down_ref: &'r RundownRef<W>,
+        count: u64,
+        holder: HolderId,
+    ) -> Self {
+        Self {
+            owned_run_down_ref,
+            count,
+            by_hand: false,
+            holder: Some(holder),
+        }
+    }
+
+    /// Returns the number of instances of run-down protection owned by this guard.
+    #[inline]
+    #[must_use]
+    pub const fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns true once rundown has been requested on the [`RundownRef`],
+    /// so a long-running holder can stop early and release its protection.
+    #[inline]
+    #[must_use]
+    pub fn is_rundown_requested(&self) -> bool {
+        self.owned_run_down_ref.is_rundown_in_progress()
+    }
+
+    /// Registers `callback` to be invoked if the thread running-down the
+    /// [`RundownRef`] calls `RundownWaiter::expedite` while the returned
+    /// registration is held, to wake a holder which is blocked.
+    ///
+    /// The callback is invoked at most once, on the thread which expedites
+    /// the rundown. Callbacks registered after the rundown was expedited are
+    /// not invoked, so register the callback before checking whether rundown
+    /// has been requested with `is_rundown_requested`.
+    #[cfg(feature = "std")]
+    pub fn on_expedite<F>(&self, callback: F) -> ExpediteRegistration<'_>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        expedite::register(self.owned_run_down_ref, Box::new(callback))
+    }
+
+    /// Splits `count` instances of run-down protection off into a new guard,
+    /// leaving the rest owned by this guard. No atomic operations are needed,
+    /// the protection simply changes owners.
+    ///
+    /// Returns `None` if `count` is zero, or if this guard does not own more
+    /// than `count` instances of protection.
+    #[must_use]
+    pub const fn split(&mut self, count: u64) -> Option<Self> {
+        if count == 0 || count >= self.count {
+            return None;
+        }
+
+        self.count -= count;
+        Some(Self {
+            owned_run_down_ref: self.owned_run_down_ref,
+            count,
+            by_hand: self.by_hand,
+            // The guards share the entry, which is removed once both dropped.
+            #[cfg(feature = "track-holders")]
+            holder: self.holder,
+        })
+    }
+}
+
+// The entry in the table of holders is left out, so the output doesn't
+// depend on the features enabled.
+#[cfg_attr(feature = "track-holders", allow(clippy::missing_fields_in_debug))]
+impl<W: WaitStrategy> fmt::Debug for RundownGuard<'_, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RundownGuard")
+            .field("count", &self.count)
+            .field("run_down_ref", self.owned_run_down_ref)
+            .finish()
+    }
+}
+
+impl<W: WaitStrategy> Drop for RundownGuard<'_, W> {
+    /// Releases all previously acquired instances of run-down protection
+    /// owned by this guard, in a single atomic operation.
+    fn drop(&mut self) {
+        if self.count > 0 {
+            // The entry is removed first, so a completed rundown has no holders.
+            #[cfg(feature = "track-holders")]
+            if let Some(holder) = self.holder {
+                self.owned_run_down_ref.untrack(holder, self.count);
+            }
+
+            if self.by_hand {
+                self.owned_run_down_ref.release_many(self.count);
+            } else {
+                self.owned_run_down_ref.release_held(self.count);
+            }
+        }
+    }
+}
+
+/// The guard holding run-down protection on a [`RundownRef32`], which
+/// releases the protection when it is dropped, like [`RundownGuard`].
+///
+/// This structure is created by the `try_acquire` and `try_acquire_many`
+/// methods on `RundownRef32`.
+pub struct RundownGuard32<'r> {
+    /// The run-down reference that this guard object points to.
+    owned_run_down_ref: &'r RundownRef32,
+
+    /// The number of instances of run-down protection owned by this guard.
+    count: u32,
+}
+
+impl<'r> RundownGuard32<'r> {
+    /// Creates a new [`RundownGuard32`] which owns `count` instances of
+    /// run-down protection on the [`RundownRef32`] provided.
+    ///
+    /// # Arguments
+    ///
+    /// * `owned_run_down_ref` - The run-down reference to release when the
+    ///   guard goes out of scope.
+    ///
+    /// * `count` - The number of instances of protection owned by the guard.
+    ///
+    pub(crate) const fn new(owned_run_down_ref: &'r RundownRef32, count: u32) -> Self {
+        Self {
+            owned_run_down_ref,
+            count,
+        }
+    }
+
+    /// Returns the number of instances of run-down protection owned by this guard.
+    #[inline]
+    #[must_use]
+    pub const fn count(&self) -> u32 {
+        self.count
+    }
+}
+
+impl fmt::Debug for RundownGuard32<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RundownGuard32")
+            .field("count", &self.count)
+            .field("run_down_ref", self.owned_run_down_ref)
+            .finish()
+    }
+}
+
+impl Drop for RundownGuard32<'_> {
+    /// Releases all previously acquired instances of run-down protection
+    /// owned by this guard, in a single atomic operation.
+    fn drop(&mut self) {
+        self.owned_run_down_ref.release_held(self.count);
+    }
+}
+
+/// An owned variant of [`RundownGuard`], which holds an [`Arc`] to the
+/// run-down reference instead of borrowing it.
+///
+/// The guard has a `'static` lifetime, so it can be moved into a spawned
+/// thread or task, stored in a struct, or held across an `.await` point.
+/// When this structure is dropped the rundown protection reference that
+/// was previously acquired is released.
+///
+/// This structure is created by the `try_acquire_owned` method on `RundownRef`.
+#[cfg(feature = "std")]
+pub struct OwnedRundownGuard<W: WaitStrategy = DefaultWaitStrategy> {
+    /// The run-down reference that this guard object keeps alive.
+    owned_run_down_ref: Arc<RundownRef<W>>,
+
+    /// The entry of the guard in the table of holders.
+    #[cfg(feature = "track-holders")]
+    holder: Option<HolderId>,
+}
+
+#[cfg(feature = "std")]
+impl<W: WaitStrategy> OwnedRundownGuard<W> {
+    /// Creates a new [`OwnedRundownGuard`] which owns an instance of run-down
+    /// protection on the [`RundownRef`] provided.
+    ///
+    /// # Arguments
+    ///
+    /// * `owned_run_down_ref` - The run-down reference to release when the
+    ///   guard goes out of scope.
+    ///
+    #[cfg_attr(feature = "track-holders", allow(dead_code))]
+    pub(crate) const fn new(owned_run_down_ref: Arc<RundownRef<W>>) -> Self {
+        Self {
+            owned_run_down_ref,
+            #[cfg(feature = "track-holders")]
+            holder: None,
+        }
+    }
+
+    /// Creates a new [`OwnedRundownGuard`] which owns an instance of run-down
+    /// protection, recorded in the table of holders as `holder`.
+    #[cfg(feature = "track-holders")]
+    pub(crate) const fn tracked(owned_run_down_ref: Arc<RundownRef<W>>, holder: HolderId) -> Self {
+        Self {
+            owned_run_down_ref,
+            holder: Some(holder),
+        }
+    }
+
+    /// Returns true once rundown has been requested on the [`RundownRef`],
+    /// like `RundownGuard::is_rundown_requested`.
+    #[inline]
+    #[must_use]
+    pub fn is_rundown_requested(&self) -> bool {
+        self.owned_run_down_ref.is_rundown_in_progress()
+    }
+
+    /// Registers `callback` to be invoked if the rundown is expedited while
+    /// the returned registration is held, like `RundownGuard::on_expedite`.
+    pub fn on_expedite<F>(&self, callback: F) -> ExpediteRegistration<'_>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        expedite::register(&*self.owned_run_down_ref, Box::new(callback))
+    }
+}
+
+// Like `RundownGuard`, the entry in the table of holders is left out.
+#[cfg(feature = "std")]
+#[cfg_attr(feature = "track-holders", allow(clippy::missing_fields_in_debug))]
+impl<W: WaitStrategy> fmt::Debug for OwnedRundownGuard<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OwnedRundownGuard")
+            .field("run_down_ref", &self.owned_run_down_ref)
+            .finish()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: WaitStrategy> Drop for OwnedRundownGuard<W> {
+    /// Releases the previously acquired instance of run-down protection.
+    fn drop(&mut self) {
+        #[cfg(feature = "track-holders")]
+        if let Some(holder) = self.holder {
+            self.owned_run_down_ref.untrack(holder, 1);
+        }
+
+        self.owned_run_down_ref.release_held(1);
+    }
+}
+
+/// An RAII guard holding run-down protection on a [`CacheAwareRundownRef`].
+///
+/// When this structure is dropped (falls out of scope), the rundown
+/// protection reference is released on the shard it was acquired from.
+///
+/// This structure is created by the `try_acquire` and `try_acquire_many`
+/// methods on `CacheAwareRundownRef`.
+#[cfg(feature = "std")]
+pub struct CacheAwareRundownGuard<'r> {
+    /// The run-down reference that this guard object points too.
+    owned_run_down_ref: &'r CacheAwareRundownRef,
+
+    /// The index of the shard the reference count was acquired on.
+    shard: usize,
+
+    /// The number of instances of run-down protection owned by this guard.
+    count: u64,
+}
+
+#[cfg(feature = "std")]
+impl<'r> CacheAwareRundownGuard<'r> {
+    /// Creates a new [`CacheAwareRundownGuard`] which owns `count` instances
+    /// of run-down protection on a shard of the [`CacheAwareRundownRef`] provided.
+    ///
+    /// # Arguments
+    ///
+    /// * `owned_run_down_ref` - The run-down reference to release when the
+    ///   guard goes out of scope.
+    ///
+    /// * `shard` - The index of the shard the protection was acquired on.
+    ///
+    /// * `count` - The number of instances of protection owned by the guard.
+    ///
+    pub(crate) const fn new(
+        owned_run_down_ref: &'r CacheAwareRundownRef,
+        shard: usize,
+        count: u64,
+    ) -> Self {
+        Self {
+            owned_run_down_ref,
+            shard,
+            count,
+        }
+    }
+
+    /// Returns the number of instances of run-down protection owned by this guard.
+    #[inline]
+    #[must_use]
+    pub const fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns true once rundown has been requested on the
+    /// [`CacheAwareRundownRef`], like `RundownGuard::is_rundown_requested`.
+    #[inline]
+    #[must_use]
+    pub fn is_rundown_requested(&self) -> bool {
+        self.owned_run_down_ref.is_shard_rundown(self.shard)
+    }
+
+    /// Registers `callback` to be invoked if the rundown is expedited while
+    /// the returned registration is held, like `RundownGuard::on_expedite`.
+    pub fn on_expedite<F>(&self, callback: F) -> ExpediteRegistration<'_>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        expedite::register(self.owned_run_down_ref, Box::new(callback))
+    }
+
+    /// Splits `count` instances of run-down protection off into a new guard,
+    /// like `RundownGuard::split`.
+    #[must_use]
+    pub const fn split(&mut self, count: u64) -> Option<Self> {
+        if count == 0 || count >= self.count {
+            return None;
+        }
+
+        self.count -= count;
+        Some(Self {
+            owned_run_down_ref: self.owned_run_down_ref,
+            shard: self.shard,
+            count,
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Debug for CacheAwareRundownGuard<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CacheAwareRundownGuard")
+            .field("count", &self.count)
+            .field("shard", &self.shard)
+            .field("run_down_ref", self.owned_run_down_ref)
+            .finish()
+    }
+}
+
+#[cfg(feature = "std")]
+impl Drop for CacheAwareRundownGuard<'_> {
+    /// Releases all previously acquired instances of run-down protection
+    /// owned by this guard, in a single atomic operation.
+    fn drop(&mut self) {
+        if self.count > 0 {
+            self.owned_run_down_ref.release_held(self.shard, self.count);
+        }
+    }
+}
+
+/// An RAII guard holding run-down protection on a [`CacheAwareRundownRef`],
+/// which owns a clone of the [`Arc`] wrapping it, like [`OwnedRundownGuard`].
+///
+/// This structure is created by the `try_acquire_owned` method on
+/// `CacheAwareRundownRef`.
+#[cfg(feature = "std")]
+pub struct OwnedCacheAwareRundownGuard {
+    /// The run-down reference that this guard object keeps alive.
+    owned_run_down_ref: Arc<CacheAwareRundownRef>,
+
+    /// The index of the shard the reference count was acquired on.
+    shard: usize,
+}
+
+#[cfg(feature = "std")]
+impl OwnedCacheAwareRundownGuard {
+    /// Creates a new [`OwnedCacheAwareRundownGuard`] which owns an instance of
+    /// run-down protection on a shard of the [`CacheAwareRundownRef`] provided.
+    ///
+    /// # Arguments
+    ///
+    /// * `owned_run_down_ref` - The run-down reference to release when the
+    ///   guard goes out of scope.
+    ///
+    /// * `shard` - The index of the shard the protection was acquired on.
+    ///
+    pub(crate) const fn new(owned_run_down_ref: Arc<CacheAwareRundownRef>, shard: usize) -> Self {
+        Self {
+            owned_run_down_ref,
+            shard,
+        }
+    }
+
+    /// Returns true once rundown has been requested on the
+    /// [`CacheAwareRundownRef`], like `RundownGuard::is_rundown_requested`.
+    #[inline]
+    #[must_use]
+    pub fn is_rundown_requested(&self) -> bool {
+        self.owned_run_down_ref.is_shard_rundown(self.shard)
+    }
+
+    /// Registers `callback` to be invoked if the rundown is expedited while
+    /// the returned registration is held, like `RundownGuard::on_expedite`.
+    pub fn on_expedite<F>(&self, callback: F) -> ExpediteRegistration<'_>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        expedite::register(&*self.owned_run_down_ref, Box::new(callback))
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Debug for OwnedCacheAwareRundownGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OwnedCacheAwareRundownGuard")
+            .field("shard", &self.shard)
+            .field("run_down_ref", &self.owned_run_down_ref)
+            .finish()
     }
 }
 
-impl<'r> Drop for RundownGuard<'r> {
+#[cfg(feature = "std")]
+impl Drop for OwnedCacheAwareRundownGuard {
     /// Releases the previously acquired instance of run-down protection.
     fn drop(&mut self) {
-        self.owned_run_down_ref.release()
+        self.owned_run_down_ref.release_held(self.shard, 1);
     }
 }
@@ -0,0 +1,136 @@
+// Copyright 2019 Brian Gianforcaro
+
+//! Tracking of the guards holding run-down protection, enabled by the
+//! `track-holders` feature, to find the code holding up a rundown.
+
+use crate::sync::{Mutex, MutexGuard};
+use core::fmt;
+use std::{
+    backtrace::Backtrace,
+    sync::{Arc, PoisonError},
+    thread,
+    thread::ThreadId,
+    time::Instant,
+};
+
+/// Identifies the entry of a guard in the table of holders. Guards split
+/// off another guard share its entry.
+pub type HolderId = u64;
+
+/// A guard holding run-down protection, as recorded when the protection
+/// was acquired. Returned by `RundownRef::holders`.
+#[derive(Debug, Clone)]
+pub struct RundownHolder {
+    /// The thread which acquired the protection.
+    pub thread_id: ThreadId,
+
+    /// The name of the thread which acquired the protection, if it has one.
+    pub thread_name: Option<String>,
+
+    /// When the protection was acquired.
+    pub acquired_at: Instant,
+
+    /// The number of references held, including those split off into
+    /// other guards which have not been dropped yet.
+    pub count: u64,
+
+    /// The stack of the thread at the point the protection was acquired.
+    pub backtrace: Arc<Backtrace>,
+}
+
+impl fmt::Display for RundownHolder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} reference(s) held for {:?} by thread {:?} ({}), acquired at:",
+            self.count,
+            self.acquired_at.elapsed(),
+            self.thread_id,
+            self.thread_name.as_deref().unwrap_or("<unnamed>"),
+        )?;
+
+        write!(f, "{}", self.backtrace)
+    }
+}
+
+/// The entries in a [`HolderTable`], protected by its lock.
+#[derive(Default)]
+struct Entries {
+    /// The identifier handed to the next holder.
+    next_id: HolderId,
+
+    /// The live holders, with their identifiers, in the order they acquired.
+    holders: Vec<(HolderId, RundownHolder)>,
+}
+
+/// The guards holding run-down protection on a run-down reference.
+#[derive(Default)]
+pub struct HolderTable {
+    /// The live holders, behind a lock as tracking is a debugging aid.
+    entries: Mutex<Entries>,
+}
+
+impl HolderTable {
+    const_fn! {
+        /// Creates a table without any holders.
+        pub fn new() -> Self {
+            Self {
+                entries: Mutex::new(Entries {
+                    next_id: 0,
+                    holders: Vec::new(),
+                }),
+            }
+        }
+    }
+
+    /// Records `count` references acquired by the calling thread, and
+    /// returns the identifier of the entry.
+    pub fn track(&self, count: u64) -> HolderId {
+        let current = thread::current();
+        let holder = RundownHolder {
+            thread_id: current.id(),
+            thread_name: current.name().map(String::from),
+            acquired_at: Instant::now(),
+            count,
+            backtrace: Arc::new(Backtrace::force_capture()),
+        };
+
+        let mut entries = self.lock();
+        let id = entries.next_id;
+        entries.next_id += 1;
+        entries.holders.push((id, holder));
+        drop(entries);
+
+        id
+    }
+
+    /// Removes `count` references from the entry `id`, and the entry
+    /// itself once all of its references are released.
+    pub fn untrack(&self, id: HolderId, count: u64) {
+        let mut entries = self.lock();
+
+        if let Some(index) = entries.holders.iter().position(|entry| entry.0 == id) {
+            let holder = &mut entries.holders[index].1;
+            holder.count = holder.count.saturating_sub(count);
+
+            if holder.count == 0 {
+                entries.holders.remove(index);
+            }
+        }
+    }
+
+    /// Returns the live holders, in the order they acquired protection.
+    pub fn holders(&self) -> Vec<RundownHolder> {
+        self.lock()
+            .holders
+            .iter()
+            .map(|entry| entry.1.clone())
+            .collect()
+    }
+
+    /// Acquires the lock on the entries. The entries are always left in
+    /// a consistent state, so poisoning is ignored.
+    fn lock(&self) -> MutexGuard<'_, Entries> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -52,23 +52,126 @@
 //! println!("0: Rundown complete");
 //! ```
 //!
+//! # Features
+//!
+//! - `std` (default): Adds blocking waits, `RundownRef::wait_for_rundown_timeout`,
+//!   `RundownRef::try_acquire_owned`, `Rundown` and `CacheAwareRundownRef`.
+//!   Without it the crate is `#![no_std]`, and [`RundownRef::wait_for_rundown`]
+//!   spins until rundown completes. A custom wait, such as yielding to a
+//!   scheduler, can be supplied via [`RundownRef::wait_for_rundown_with`].
+//!
+//! - `async`: Adds `RundownRef::wait_for_rundown_async`, a runtime agnostic
+//!   future which completes once rundown is complete.
+//!
+//! - `track-holders`: Records the thread, time and backtrace of every
+//!   acquisition of a [`RundownGuard`], listed by `RundownRef::holders`, and
+//!   adds `RundownRef::wait_for_rundown_or_dump` which prints them when the
+//!   wait times out. Only intended for debugging, as acquisitions slow down.
+//!
+//! - `loom`: Swaps the synchronization primitives used by the crate for their
+//!   [`loom`][loom-link] counterparts. Only intended for running the model
+//!   checking tests, via `cargo test --release --features loom --test loom`.
+//!
 //! [nt-run-down-docs]: https://docs.microsoft.com/en-us/windows-hardware/drivers/kernel/run-down-protection
+//! [loom-link]: https://docs.rs/loom
 //! [smp-link]: https://en.wikipedia.org/wiki/Symmetric_multiprocessing
 
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 // Force "Allow" lints to be warnings, then re-disable specific warnings, for
 // issues we don't necessarily care about for this project.
 //
 #![warn(clippy::all, clippy::pedantic, clippy::nursery, clippy::cargo)]
-#![allow(clippy::module_name_repetitions)]
+#![allow(clippy::module_name_repetitions, clippy::multiple_crate_versions)]
+
+/// Defines a `const fn`, unless the `loom` feature is enabled, as the
+/// `loom` primitives can't be constructed in a const context.
+macro_rules! const_fn {
+    (
+        $(#[$attr:meta])*
+        $vis:vis fn $name:ident($($arg:ident: $arg_ty:ty),*) -> $ret:ty $body:block
+    ) => {
+        #[cfg(not(feature = "loom"))]
+        $(#[$attr])*
+        $vis const fn $name($($arg: $arg_ty),*) -> $ret $body
+
+        #[cfg(feature = "loom")]
+        $(#[$attr])*
+        $vis fn $name($($arg: $arg_ty),*) -> $ret $body
+    };
+}
 
+#[cfg(feature = "std")]
+mod cache_aware;
+#[cfg(feature = "std")]
+mod callbacks;
+mod drop_on_rundown;
+#[cfg(feature = "std")]
+mod event;
+#[cfg(feature = "std")]
+mod expedite;
 mod flags;
+mod flags32;
+#[cfg(all(feature = "std", target_os = "linux", not(feature = "loom")))]
+mod futex;
+#[cfg(feature = "async")]
+mod future;
 mod guard;
+#[cfg(feature = "track-holders")]
+mod holders;
+#[cfg(all(feature = "std", any(not(target_os = "linux"), feature = "loom")))]
+mod parking;
+#[cfg(feature = "std")]
+mod rundown;
 mod rundown_ref;
+mod rundown_ref32;
+mod sync;
+#[cfg(feature = "std")]
+mod table;
+mod tagged;
+mod wait;
+mod waiter;
 
+#[cfg(feature = "std")]
+pub use crate::cache_aware::CacheAwareRundownRef;
+pub use crate::drop_on_rundown::DropOnRundown;
+pub use crate::drop_on_rundown::DropOnRundownGuard;
+#[cfg(feature = "std")]
+pub use crate::expedite::ExpediteRegistration;
+pub use crate::flags::RundownState;
+#[cfg(feature = "async")]
+pub use crate::future::CacheAwareRundownFuture;
+#[cfg(feature = "async")]
+pub use crate::future::RundownFuture;
+#[cfg(feature = "std")]
+pub use crate::guard::CacheAwareRundownGuard;
+#[cfg(feature = "std")]
+pub use crate::guard::OwnedCacheAwareRundownGuard;
+#[cfg(feature = "std")]
+pub use crate::guard::OwnedRundownGuard;
 pub use crate::guard::RundownGuard;
+pub use crate::guard::RundownGuard32;
+#[cfg(feature = "track-holders")]
+pub use crate::holders::RundownHolder;
+#[cfg(feature = "std")]
+pub use crate::rundown::Rundown;
+#[cfg(feature = "std")]
+pub use crate::rundown::RundownAccessGuard;
 pub use crate::rundown_ref::RundownError;
 pub use crate::rundown_ref::RundownRef;
+pub use crate::rundown_ref::RundownSnapshot;
+pub use crate::rundown_ref::RundownWaitResult;
+pub use crate::rundown_ref::MAX_REF_COUNT;
+pub use crate::rundown_ref32::RundownRef32;
+pub use crate::tagged::RundownTag;
+pub use crate::tagged::TaggedRundownGuard;
+pub use crate::tagged::TaggedRundownRef;
+#[cfg(feature = "std")]
+pub use crate::wait::{AdaptiveWait, EventWait, ParkWait, YieldWait};
+pub use crate::wait::{DefaultWaitStrategy, SpinWait, WaitContext, WaitStrategy};
+#[cfg(feature = "std")]
+pub use crate::waiter::CacheAwareRundownWaiter;
+pub use crate::waiter::RundownWaiter;
 
 // Test examples in the README file.
-#[cfg(doctest)]
+#[cfg(all(doctest, feature = "std", not(feature = "loom")))]
 doc_comment::doctest!("../README.md", readme_examples);
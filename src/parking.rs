@@ -0,0 +1,23 @@
+// Copyright 2019 Brian Gianforcaro
+
+//! A global table of events for objects which are too small to embed an
+//! event of their own, keyed by the address of the object.
+//!
+//! Objects whose addresses map to the same bucket share its event, so a
+//! waiter can be woken by the completion of an unrelated rundown, in which
+//! case it evaluates its condition again and goes back to waiting.
+
+use crate::{
+    event::RundownEvent,
+    table::{address_table, Key},
+};
+
+address_table! {
+    /// The events objects wait on, indexed by the bucket of their address.
+    static EVENTS: RundownEvent = RundownEvent::new();
+}
+
+/// Returns the event the waiters for rundown on `object` wait on.
+pub fn event_for<T>(object: &T) -> &'static RundownEvent {
+    Key::of(object).bucket(&EVENTS)
+}
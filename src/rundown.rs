@@ -0,0 +1,200 @@
+// Copyright 2019 Brian Gianforcaro
+
+use crate::{
+    flags::RundownState, guard::RundownGuard, rundown_ref::RundownError, rundown_ref::RundownRef,
+    sync::Mutex, sync::MutexGuard,
+};
+use std::{cell::UnsafeCell, ops::Deref, sync::PoisonError};
+
+/// A container which owns a value alongside the [`RundownRef`] protecting
+/// it, so that the value can only be reached through run-down protection.
+///
+/// Shared access is obtained with `try_access`, which fails once rundown has
+/// started. Exclusive access is only handed out by `run_down_with` and
+/// `run_down_and_take`, after all outstanding shared access has drained.
+///
+/// # Example
+///
+/// ```rust
+/// use run_down::Rundown;
+///
+/// let config = Rundown::new(String::from("v1"));
+///
+/// if let Ok(value) = config.try_access() {
+///     assert_eq!("v1", value.as_str());
+/// }
+///
+/// // Wait for all readers to drain, then update the value.
+/// config.run_down_with(|value| *value = String::from("v2"));
+/// assert!(config.try_access().is_err());
+///
+/// // Allow access to the new value.
+/// config.re_init();
+/// assert_eq!("v2", config.try_access().unwrap().as_str());
+/// ```
+pub struct Rundown<T> {
+    /// The run-down reference protecting access to the data.
+    protection: RundownRef,
+
+    /// Serializes the threads which require exclusive access to the data,
+    /// so that only one of them can run-down or re-initialize at a time.
+    exclusive: Mutex<()>,
+
+    /// The data protected by the run-down reference.
+    data: UnsafeCell<T>,
+}
+
+// Safety: The data is shared between threads through `try_access`, and can
+// be mutated by any thread through `run_down_with`, so like `RwLock` the data
+// must be both `Send` and `Sync`.
+unsafe impl<T: Send + Sync> Sync for Rundown<T> {}
+
+impl<T> Rundown<T> {
+    const_fn! {
+        /// Initializes a new [`Rundown`] protecting the provided value.
+        #[inline]
+        #[must_use]
+        pub fn new(data: T) -> Self {
+            Self {
+                protection: RundownRef::new(),
+                exclusive: Mutex::new(()),
+                data: UnsafeCell::new(data),
+            }
+        }
+    }
+
+    /// Attempts to acquire rundown protection on the data, returns the
+    /// [`RundownAccessGuard`] which dereferences to the data, or returns an
+    /// error if the data is already being rundown.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the rundown is already in progress on the data.
+    ///
+    pub fn try_access(&self) -> Result<RundownAccessGuard<'_, T>, RundownError> {
+        let guard = self.protection.try_acquire()?;
+
+        // Safety: Exclusive access is only handed out once rundown is
+        // complete, which can't happen while this guard is outstanding.
+        let data = unsafe { &*self.data.get() };
+
+        Ok(RundownAccessGuard {
+            data,
+            _guard: guard,
+        })
+    }
+
+    /// Blocks thread execution until the data has been completely run-down,
+    /// then invokes `f` with exclusive access to the data.
+    ///
+    /// The data stays run-down once this method returns, `re_init` must be
+    /// called to allow access to the data again.
+    pub fn run_down_with<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        let _exclusive = self.lock_exclusive();
+
+        self.protection.wait_for_rundown();
+
+        // Safety: Rundown is complete, so there are no outstanding guards
+        // and none can be acquired until `re_init` is called, which is
+        // serialized with this method by the exclusive lock.
+        f(unsafe { &mut *self.data.get() })
+    }
+
+    /// Blocks thread execution until the data has been completely run-down,
+    /// then takes the data, leaving the default value in its place.
+    ///
+    /// The data stays run-down once this method returns, `re_init` must be
+    /// called to allow access to the data again.
+    pub fn run_down_and_take(&self) -> T
+    where
+        T: Default,
+    {
+        self.run_down_with(std::mem::take)
+    }
+
+    /// Re-initialize the protection so the data can be accessed again. It is
+    /// only valid to call `re_init` once the data is completely run-down, via
+    /// the `run_down_with` or `run_down_and_take` methods.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the data has not been run-down.
+    pub fn re_init(&self) {
+        let _exclusive = self.lock_exclusive();
+
+        self.protection.re_init();
+    }
+
+    /// Re-initialize the protection so the data can be accessed again, like
+    /// `re_init`, but returns an error instead of panicking if the data has
+    /// not been run-down.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err(RundownError::NotRunDown)` if the data has not been run-down.
+    ///
+    pub fn try_re_init(&self) -> Result<(), RundownError> {
+        let _exclusive = self.lock_exclusive();
+
+        self.protection.try_re_init()
+    }
+
+    /// Returns the current state of run-down protection on the data.
+    #[inline]
+    pub fn state(&self) -> RundownState {
+        self.protection.state()
+    }
+
+    /// Returns a mutable reference to the data. No run-down protection is
+    /// required, as the mutable borrow guarantees no other access exists.
+    #[inline]
+    pub const fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// Consumes the [`Rundown`], returning the data.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+
+    /// Acquires the lock serializing exclusive access to the data. The lock
+    /// guards no data of its own, so poisoning can safely be ignored.
+    fn lock_exclusive(&self) -> MutexGuard<'_, ()> {
+        self.exclusive
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<T: Default> Default for Rundown<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// An RAII guard providing shared access to the data in a [`Rundown`].
+///
+/// The guard holds run-down protection on the data, which is released
+/// when the guard is dropped (falls out of scope).
+///
+/// This structure is created by the `try_access` method on `Rundown`.
+pub struct RundownAccessGuard<'r, T> {
+    /// The data protected by the run-down reference.
+    data: &'r T,
+
+    /// The run-down protection held on behalf of this guard.
+    _guard: RundownGuard<'r>,
+}
+
+impl<T> Deref for RundownAccessGuard<'_, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        self.data
+    }
+}
@@ -1,27 +1,131 @@
 // Copyright 2019 Brian Gianforcaro
 
-use crate::{flags::to_flags, flags::RundownFlags, guard::RundownGuard};
-use lazy_init::Lazy;
-use rsevents::{Awaitable, ManualResetEvent, State};
-use std::{result::Result, sync::atomic::AtomicU64, sync::atomic::Ordering};
+#[cfg(all(feature = "async", target_os = "linux", not(feature = "loom")))]
+use crate::futex;
+#[cfg(feature = "async")]
+use crate::future::RundownFuture;
+#[cfg(feature = "std")]
+use crate::{callbacks, callbacks::Callback, guard::OwnedRundownGuard};
+use crate::{
+    flags::to_flags, flags::RundownFlags, flags::RundownState, guard::RundownGuard, sync::fence,
+    sync::spin_loop, sync::AtomicU64, wait::DefaultWaitStrategy, wait::WaitContext,
+    wait::WaitStrategy, waiter::RundownWaiter,
+};
+#[cfg(feature = "track-holders")]
+use crate::{holders::HolderId, holders::HolderTable, holders::RundownHolder};
+use core::{fmt, result::Result, sync::atomic::Ordering, time::Duration};
+#[cfg(feature = "async")]
+use std::task::Waker;
+#[cfg(feature = "std")]
+use std::{sync::Arc, time::Instant};
+
+/// The largest number of references acquired with a single atomic increment.
+/// Larger counts are acquired with a compare and exchange loop instead, so
+/// increments which need to be backed out stay within the headroom above
+/// `RundownFlags::MAX_REF_COUNT`, and never run into the flag bits.
+const MAX_SPECULATIVE_ACQUIRE: u64 = 1 << 32;
+
+/// The shortest interval between two reports of `wait_for_rundown_with_progress`.
+#[cfg(feature = "std")]
+const MIN_PROGRESS_INTERVAL: Duration = Duration::from_millis(1);
+
+/// The maximum number of references which can be outstanding on a
+/// [`RundownRef`] at once, whatever its [`WaitStrategy`].
+pub const MAX_REF_COUNT: u64 = RundownFlags::MAX_REF_COUNT;
 
 /// The set of errors returned by methods in the run-down crate.
-#[derive(Debug, PartialEq)]
+///
+/// More errors may be added in the future, so matches on it need a
+/// wildcard arm.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
 pub enum RundownError {
     /// Rundown is already in progress on this shared object.
     RundownInProgress,
+
+    /// The shared object can't be re-initialized, as it has not been run-down.
+    NotRunDown,
+
+    /// The shared object can't be re-initialized, as rundown is in progress
+    /// but there are still outstanding references on it.
+    ReferencesOutstanding,
+
+    /// Protection can't be acquired, as the reference count would exceed
+    /// the maximum number of outstanding references.
+    TooManyReferences,
+
+    /// Protection can't be released, as fewer references are outstanding
+    /// than are being released.
+    ReferenceUnderflow,
+
+    /// Protection can't be acquired with the tag, as the maximum number
+    /// of distinct tags are already being counted.
+    TooManyTags,
+
+    /// The shared object can't be re-initialized by a callback registered
+    /// with `run_down_then`, as the callbacks of the rundown are still running.
+    CallbacksRunning,
+}
+
+impl fmt::Display for RundownError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            Self::RundownInProgress => "rundown is already in progress",
+            Self::NotRunDown => "the object has not been run-down",
+            Self::ReferencesOutstanding => "rundown is in progress, but references are outstanding",
+            Self::TooManyReferences => "the maximum number of references are outstanding",
+            Self::ReferenceUnderflow => "more references were released than are outstanding",
+            Self::TooManyTags => "the maximum number of tags are already being counted",
+            Self::CallbacksRunning => "the callbacks of the rundown are still running",
+        };
+
+        f.write_str(message)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RundownError {}
+
+/// The outcome of waiting for rundown with a timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[must_use]
+pub enum RundownWaitResult {
+    /// Rundown completed, there are no outstanding references.
+    Complete,
+
+    /// The timeout elapsed before rundown completed.
+    TimedOut {
+        /// The number of references still outstanding when the wait timed out.
+        outstanding: u64,
+    },
+}
+
+/// A point in time view of the state of run-down protection on an object,
+/// returned by `snapshot`. Both parts are read with a single atomic load, so
+/// they are consistent with each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RundownSnapshot {
+    /// The state of run-down protection on the object.
+    pub state: RundownState,
+
+    /// The number of references which were outstanding on the object.
+    pub outstanding_refs: u64,
 }
 
 /// Tracks the status of run-down protection for an object.
 /// The type would be embedded in the object needing run-down protection.
+///
+/// How threads wait for rundown is decided by the [`WaitStrategy`] `W`. With
+/// the default strategy on Linux, waiting threads block on a futex on the
+/// reference count itself, so the type is exactly 8 bytes and never allocates.
 #[derive(Default)]
-pub struct RundownRef {
+pub struct RundownRef<W: WaitStrategy = DefaultWaitStrategy> {
     /// The reference count used to track the threads that currently have
     /// outstanding run-down protection request being tracked by this object.
     ///
     /// The reference count holds two parts, the actual count in the lower bits
-    /// and the flags bit in the most significant bit of the u64. The flags and
-    /// reference count interpretation logic is encapsulated in the RundownFlags
+    /// and the flags in the most significant byte of the u64. The flags and
+    /// reference count interpretation logic is encapsulated in the `RundownFlags`
     /// type. It has the logic to correctly mask and fetch the required bits.
     ///
     /// We need to bit-pack the flags with the reference count, as we need a single
@@ -29,20 +133,103 @@ pub struct RundownRef {
     /// provide the thread safety guaranteed by this type.
     ref_count: AtomicU64,
 
-    /// The event used to signal the thread waiting for rundown that
-    /// rundown is now complete.
-    ///
-    /// The event is lazy initialized to avoid allocating the event
-    /// unless there is an active reference count when rundown starts.
-    event: Lazy<ManualResetEvent>,
+    /// The strategy used to block the threads waiting for rundown,
+    /// and to wake them once rundown is complete.
+    strategy: W,
+
+    /// The guards currently holding protection, with the `track-holders`
+    /// feature, which makes the type larger than the reference count.
+    #[cfg(feature = "track-holders")]
+    holders: HolderTable,
 }
 
 impl RundownRef {
-    /// Initializes a new [`RundownRef`].
-    #[inline]
-    #[must_use]
-    pub fn new() -> Self {
-        Self::default()
+    /// The maximum number of references which can be outstanding at once.
+    /// Code generic over the [`WaitStrategy`] can use [`crate::MAX_REF_COUNT`],
+    /// which is the same for every strategy.
+    pub const MAX_REF_COUNT: u64 = MAX_REF_COUNT;
+
+    const_fn! {
+        /// Initializes a new [`RundownRef`], which uses the default [`WaitStrategy`].
+        ///
+        /// The default strategy doesn't allocate until a thread waits, so
+        /// the reference can be used to initialize a `static`:
+        ///
+        /// ```rust
+        /// use run_down::RundownRef;
+        ///
+        /// static SHUTDOWN: RundownRef = RundownRef::new();
+        ///
+        /// if let Ok(_guard) = SHUTDOWN.try_acquire() {
+        ///     // The process is not shutting down yet.
+        /// }
+        ///
+        /// SHUTDOWN.wait_for_rundown();
+        /// ```
+        #[inline]
+        #[must_use]
+        pub fn new() -> Self {
+            Self::with_wait_strategy(DefaultWaitStrategy::new())
+        }
+    }
+
+    /// Returns a future which completes once there are no outstanding
+    /// reference counts taken on the [`RundownRef`]. Like `wait_for_rundown`
+    /// the object is marked as 'rundown in progress' so that no other
+    /// thread can acquire a reference count afterwards.
+    ///
+    /// The future is runtime agnostic, the thread which releases the last
+    /// outstanding reference wakes the task which is awaiting the future.
+    ///
+    /// # Important
+    ///
+    /// - The object is marked as 'rundown in progress' when the future is
+    ///   first polled, not when this method is called.
+    ///
+    /// - No memory is allocated if there are no outstanding references
+    ///   when the future is polled.
+    ///
+    #[cfg(feature = "async")]
+    pub const fn wait_for_rundown_async(&self) -> RundownFuture<'_> {
+        RundownFuture::new(self)
+    }
+
+    /// Drives the [`RundownFuture`] returned by `wait_for_rundown_async`,
+    /// returns true once rundown is complete, otherwise registers the waker
+    /// to be woken by the thread which releases the last reference, keeping
+    /// the registration in `task`.
+    #[cfg(feature = "async")]
+    pub(crate) fn poll_rundown(&self, task: &mut Option<u64>, waker: &Waker) -> bool {
+        let current = self.mark_rundown_in_progress();
+
+        current.is_rundown_complete() || !self.strategy.register(&self.context(), task, waker)
+    }
+
+    /// Removes the registration `task` of a [`RundownFuture`] which is
+    /// dropped, so its waker isn't kept until rundown completes.
+    #[cfg(feature = "async")]
+    pub(crate) fn cancel_poll(&self, task: u64) {
+        self.strategy.unregister(&self.context(), task);
+    }
+}
+
+impl<W: WaitStrategy> RundownRef<W> {
+    const_fn! {
+        /// Initializes a new [`RundownRef`], which waits for rundown
+        /// using the [`WaitStrategy`] provided.
+        ///
+        /// # Arguments
+        ///
+        /// * `strategy` - Decides how threads wait for rundown to complete.
+        ///
+        pub fn with_wait_strategy(strategy: W) -> Self {
+            Self {
+                ref_count: AtomicU64::new(0),
+                strategy,
+                #[cfg(feature = "track-holders")]
+                holders: HolderTable::new(),
+            }
+        }
     }
 
     /// Re-initialize this instance so it can be used again. It is only valid
@@ -51,35 +238,119 @@ impl RundownRef {
     ///
     /// # Important
     ///
-    /// The moment this method returns, new rundown protection requests can
-    /// succeed. You must perform all re-initialization of the shared object
-    /// the run-down protection is guarding before you call this method.
+    /// - The moment this method returns, new rundown protection requests can
+    ///   succeed. You must perform all re-initialization of the shared object
+    ///   the run-down protection is guarding before you call this method.
+    ///
+    /// - If the callbacks registered with `run_down_then` are still running,
+    ///   this method waits for them to finish first.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the object has not been run-down, if there are still
+    /// outstanding references on the object, or if it is called by one
+    /// of the callbacks registered with `run_down_then`.
     pub fn re_init(&self) {
-        let current = self.load_flags();
-
-        // Validate that the object in the correct state.
-        //
-        // TODO: Ideally we should have another bit to represent
-        // rundown being complete vs run-down in progress. It would
-        // give us a more clear state transition.
-        //
-        if current.is_pre_rundown() || current.is_ref_active() {
-            panic!("Attempt to re-init before rundown is complete");
+        if let Err(error) = self.try_re_init() {
+            panic!("Attempt to re-init before rundown is complete: {:?}", error);
         }
+    }
+
+    /// Re-initialize this instance so it can be used again, like `re_init`,
+    /// but returns an error instead of panicking if rundown is not complete.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err(RundownError::NotRunDown)` if rundown has not been
+    /// started, or `Err(RundownError::ReferencesOutstanding)` if rundown is
+    /// in progress but there are still outstanding references on the object.
+    /// Will return `Err(RundownError::CallbacksRunning)` if it is called by
+    /// one of the callbacks registered with `run_down_then`.
+    ///
+    pub fn try_re_init(&self) -> Result<(), RundownError> {
+        let mut current = self.load_flags();
+
+        loop {
+            // Validate that the object in the correct state.
+            match current.state() {
+                RundownState::Active => return Err(RundownError::NotRunDown),
+                RundownState::RundownInProgress => return Err(RundownError::ReferencesOutstanding),
+                RundownState::RundownComplete => {}
+            }
+
+            // A callback can't wait for the callbacks to finish running.
+            #[cfg(feature = "std")]
+            if current.has_callbacks() && callbacks::is_running(self) {
+                return Err(RundownError::CallbacksRunning);
+            }
 
-        // Reset the event if it was previously lazily created so it
-        // can be used again in the future. If the event doesn't exist
-        // yet, then there is nothing to do.
-        if let Some(event) = self.event.get() {
-            event.reset();
+            // Once complete, references are only held briefly by acquisitions
+            // which are about to back out, and the callbacks flag until the
+            // callbacks have run, so wait for them to be cleared.
+            if current.is_ref_active() || current.has_callbacks() {
+                spin_loop();
+                current = self.load_flags();
+                continue;
+            }
+
+            // Zero the reference count to make the object ready for use.
+            //
+            // Note: Once this exchange completes then new instances of run-down
+            // protection will be able to be acquired immediately. All
+            // validation and re-initialization needs to occur before this point.
+            //
+            // If another thread re-initialized the object first, the exchange
+            // fails and the state is validated again.
+            match self.compare_exchange(current.bits(), 0) {
+                Ok(_) => return Ok(()),
+                Err(new_current) => current = to_flags(new_current),
+            }
         }
+    }
+
+    /// Returns the current state of run-down protection on this [`RundownRef`].
+    #[inline]
+    #[must_use]
+    pub fn state(&self) -> RundownState {
+        to_flags(self.ref_count.load(Ordering::Acquire)).state()
+    }
+
+    /// Returns the number of references currently outstanding on this
+    /// [`RundownRef`]. The count can briefly include acquisitions which
+    /// are about to fail, as they back out their reference.
+    #[inline]
+    #[must_use]
+    pub fn outstanding_refs(&self) -> u64 {
+        to_flags(self.ref_count.load(Ordering::Acquire)).get_ref()
+    }
+
+    /// Returns true if rundown has started, but there are still outstanding
+    /// references, so it has not completed yet.
+    #[inline]
+    #[must_use]
+    pub fn is_rundown_in_progress(&self) -> bool {
+        self.state() == RundownState::RundownInProgress
+    }
+
+    /// Returns true if rundown has completed, and the object has not
+    /// been re-initialized since.
+    #[inline]
+    #[must_use]
+    pub fn is_rundown_complete(&self) -> bool {
+        self.state() == RundownState::RundownComplete
+    }
+
+    /// Returns the state of run-down protection on this [`RundownRef`]
+    /// and the number of outstanding references, as of a single point in time.
+    #[inline]
+    #[must_use]
+    pub fn snapshot(&self) -> RundownSnapshot {
+        let current = to_flags(self.ref_count.load(Ordering::Acquire));
 
-        // Zero the reference count to make the object ready for use.
-        //
-        // Note: Once this store completes then new instances of run-down
-        // protection will be able to be acquired immediately. All
-        // validation and re-initialization needs to occur before this point.
-        self.ref_count.store(0, Ordering::Release);
+        RundownSnapshot {
+            state: current.state(),
+            outstanding_refs: current.get_ref(),
+        }
     }
 
     /// Attempts to acquire rundown protection on this [`RundownRef`], returns
@@ -88,9 +359,105 @@ impl RundownRef {
     ///
     /// # Errors
     ///
-    /// Will return `Err` if the rundown is already in progress on the object.
+    /// Will return `Err(RundownError::RundownInProgress)` if the rundown is
+    /// already in progress on the object, or `Err(RundownError::TooManyReferences)`
+    /// if `MAX_REF_COUNT` references are already outstanding.
+    ///
+    pub fn try_acquire(&self) -> Result<RundownGuard<'_, W>, RundownError> {
+        self.acquire(1)?;
+
+        #[cfg(feature = "track-holders")]
+        return Ok(RundownGuard::tracked(self, 1, self.holders.track(1)));
+
+        #[cfg(not(feature = "track-holders"))]
+        Ok(RundownGuard::with_count(self, 1))
+    }
+
+    /// Attempts to acquire `count` instances of rundown protection on this
+    /// [`RundownRef`] in a single atomic operation. Returns the [`RundownGuard`]
+    /// which holds all of the reference counts, or returns an error if the
+    /// object is already being rundown.
+    ///
+    /// The guard can be split into smaller guards with `RundownGuard::split`,
+    /// each of which releases its share of the references when dropped.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err(RundownError::RundownInProgress)` if the rundown is
+    /// already in progress on the object, or `Err(RundownError::TooManyReferences)`
+    /// if acquiring `count` references would exceed `MAX_REF_COUNT`.
+    ///
+    pub fn try_acquire_many(&self, count: u64) -> Result<RundownGuard<'_, W>, RundownError> {
+        self.acquire(count)?;
+
+        #[cfg(feature = "track-holders")]
+        return Ok(RundownGuard::tracked(
+            self,
+            count,
+            self.holders.track(count),
+        ));
+
+        #[cfg(not(feature = "track-holders"))]
+        Ok(RundownGuard::with_count(self, count))
+    }
+
+    /// Attempts to acquire rundown protection on this [`RundownRef`], returns
+    /// the [`OwnedRundownGuard`] which holds the reference count, or returns an
+    /// error if the object is already being rundown.
     ///
-    pub fn try_acquire(&self) -> Result<RundownGuard<'_>, RundownError> {
+    /// Unlike the [`RundownGuard`] returned by `try_acquire`, the owned guard
+    /// holds a clone of the [`Arc`], so it can be moved to another thread or
+    /// held across an `.await` point.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err(RundownError::RundownInProgress)` if the rundown is
+    /// already in progress on the object, or `Err(RundownError::TooManyReferences)`
+    /// if `MAX_REF_COUNT` references are already outstanding.
+    ///
+    #[cfg(feature = "std")]
+    pub fn try_acquire_owned(self: &Arc<Self>) -> Result<OwnedRundownGuard<W>, RundownError> {
+        self.acquire(1)?;
+
+        #[cfg(feature = "track-holders")]
+        return Ok(OwnedRundownGuard::tracked(
+            Arc::clone(self),
+            self.holders.track(1),
+        ));
+
+        #[cfg(not(feature = "track-holders"))]
+        Ok(OwnedRundownGuard::new(Arc::clone(self)))
+    }
+
+    /// Acquires `count` reference counts on behalf of a guard, the guard
+    /// is responsible for releasing them when it goes out of scope.
+    fn acquire(&self, count: u64) -> Result<(), RundownError> {
+        if count > MAX_SPECULATIVE_ACQUIRE {
+            return self.acquire_exact(count);
+        }
+
+        // Optimistically take the references with a single atomic increment,
+        // and back out if rundown had already started. Acquiring pairs with
+        // the exchange which re-initialized the object.
+        let previous = to_flags(self.ref_count.fetch_add(count, Ordering::Acquire));
+
+        if previous.is_rundown_in_progress() {
+            self.release_held(count);
+            return Err(RundownError::RundownInProgress);
+        }
+
+        if previous.get_ref() > MAX_REF_COUNT - count {
+            self.release_held(count);
+            return Err(RundownError::TooManyReferences);
+        }
+
+        Ok(())
+    }
+
+    /// Acquires `count` reference counts on behalf of a guard with a compare
+    /// and exchange loop, which never changes the count once rundown started.
+    #[cold]
+    fn acquire_exact(&self, count: u64) -> Result<(), RundownError> {
         let mut current = self.load_flags();
 
         loop {
@@ -98,21 +465,89 @@ impl RundownRef {
                 return Err(RundownError::RundownInProgress);
             }
 
-            let new_bits_with_ref = current.add_ref();
+            let Some(new_bits_with_ref) = current.add_refs(count) else {
+                return Err(RundownError::TooManyReferences);
+            };
 
             match self.compare_exchange(current.bits(), new_bits_with_ref) {
-                Ok(_) => return Ok(RundownGuard::new(self)),
+                Ok(_) => return Ok(()),
                 Err(new_current) => current = to_flags(new_current),
             }
         }
     }
 
     /// Release previously acquired rundown protection.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no references are outstanding.
+    ///
     pub fn release(&self) {
+        self.release_many(1);
+    }
+
+    /// Release previously acquired rundown protection, like `release`, but
+    /// returns an error instead of panicking if no references are outstanding.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err(RundownError::ReferenceUnderflow)` if no references
+    /// are outstanding, the reference count is left unchanged.
+    ///
+    pub fn try_release(&self) -> Result<(), RundownError> {
+        self.try_release_many(1)
+    }
+
+    /// Release `count` instances of previously acquired rundown protection
+    /// in a single atomic operation.
+    ///
+    /// # Panics
+    ///
+    /// Panics if fewer than `count` references are outstanding.
+    ///
+    pub fn release_many(&self, count: u64) {
+        assert!(
+            self.try_release_many(count).is_ok(),
+            "Decrementing the reference-count would have under-flowed!"
+        );
+    }
+
+    /// Release `count` references which are known to be held, by a guard or
+    /// by an acquisition which backs out, with a single atomic decrement.
+    pub(crate) fn release_held(&self, count: u64) {
+        // Releasing publishes the accesses made under protection to
+        // the thread waiting for rundown.
+        let previous = to_flags(self.ref_count.fetch_sub(count, Ordering::Release));
+
+        // Releasing the last reference while rundown is in progress
+        // is followed by marking rundown as complete.
+        let current = to_flags(previous.dec_refs(count));
+        if current.is_ref_zero()
+            && current.is_rundown_in_progress()
+            && !current.is_rundown_complete()
+        {
+            self.complete_rundown(current);
+        }
+    }
+
+    /// Release `count` instances of previously acquired rundown protection,
+    /// like `release_many`, but returns an error instead of panicking if
+    /// fewer than `count` references are outstanding.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err(RundownError::ReferenceUnderflow)` if fewer than
+    /// `count` references are outstanding, the reference count is left unchanged.
+    ///
+    pub fn try_release_many(&self, count: u64) -> Result<(), RundownError> {
         let mut current = self.load_flags();
 
+        // The count is validated before it is changed, so other threads
+        // never observe the reference count under-flowing.
         loop {
-            let bits_with_decrement = current.dec_ref();
+            let Some(bits_with_decrement) = current.checked_dec_refs(count) else {
+                return Err(RundownError::ReferenceUnderflow);
+            };
 
             match self.compare_exchange(current.bits(), bits_with_decrement) {
                 Ok(_) => {
@@ -123,9 +558,52 @@ impl RundownRef {
             }
         }
 
-        if current.is_ref_zero() && current.is_rundown_in_progress() {
-            let event = self.event.get().expect("Must have been set");
-            event.set();
+        if current.is_ref_zero()
+            && current.is_rundown_in_progress()
+            && !current.is_rundown_complete()
+        {
+            self.complete_rundown(current);
+        }
+
+        Ok(())
+    }
+
+    /// Marks rundown as complete after the last reference was released while
+    /// rundown was in progress, wakes everyone waiting for rundown, and runs
+    /// the callbacks registered with `run_down_then`.
+    #[cold]
+    fn complete_rundown(&self, mut current: RundownFlags) {
+        let completed = loop {
+            // A thread which started rundown in the meantime observed there
+            // were no references and completed rundown itself, but did not
+            // wake the waiters. The object may even be re-initialized since.
+            if current.is_pre_rundown() || current.is_rundown_complete() {
+                break None;
+            }
+
+            // An acquisition which is about to back out took a reference in
+            // the meantime, releasing it completes rundown instead.
+            if current.is_ref_active() {
+                return;
+            }
+
+            match self.compare_exchange(current.bits(), current.set_rundown_complete()) {
+                Ok(_) => break Some(current),
+                Err(new_current) => current = to_flags(new_current),
+            }
+        };
+
+        // The release only published this thread's accesses, the waiters
+        // prepared the wait strategy before marking rundown as in progress,
+        // which the loads above observed, so synchronize with them as well.
+        fence(Ordering::Acquire);
+
+        self.signal_rundown_complete();
+
+        // The callbacks run once the waiters are woken, so a callback
+        // which panics doesn't leave them blocked.
+        if completed.is_some_and(RundownFlags::has_callbacks) {
+            self.run_callbacks();
         }
     }
 
@@ -136,40 +614,410 @@ impl RundownRef {
     ///
     /// # Important
     ///
-    /// - This method is not thread safe, it must only be called by one thread.
+    /// - Any number of threads can wait for rundown concurrently, they
+    ///   are all woken once the last outstanding reference is released.
     ///
-    /// - This method is however idempotent, it can be called multiple times.
+    /// - If the object is re-initialized before a waiting thread observes
+    ///   the completed rundown, the thread returns once it sees the object
+    ///   active. Should another rundown start before then, the thread can't
+    ///   tell the two apart, and keeps waiting until that rundown completes.
+    ///
+    /// - This method is idempotent, it can be called multiple times.
+    ///
+    /// - The [`WaitStrategy`] decides how the thread waits, by default
+    ///   it blocks, or spins without the `std` feature.
     ///
     pub fn wait_for_rundown(&self) {
+        self.begin_rundown().wait();
+    }
+
+    /// Marks the [`RundownRef`] as 'rundown in progress', so that no other
+    /// thread can acquire a reference count afterwards, without waiting for
+    /// the outstanding references to be released.
+    ///
+    /// Returns the [`RundownWaiter`] used to wait for the outstanding
+    /// references, which allows other teardown to be performed while they
+    /// are released. Dropping the waiter doesn't affect the rundown.
+    ///
+    /// Like `wait_for_rundown`, this method is idempotent.
+    pub fn begin_rundown(&self) -> RundownWaiter<'_, W> {
+        self.mark_rundown_in_progress();
+        RundownWaiter::new(self)
+    }
+
+    /// Marks the [`RundownRef`] as 'rundown in progress', like `begin_rundown`,
+    /// and registers `callback` to run once rundown completes, so teardown
+    /// can be performed without a thread waiting for rundown.
+    ///
+    /// The callback runs exactly once, on the thread which releases the last
+    /// outstanding reference, or on the calling thread before this method
+    /// returns if there are no outstanding references.
+    ///
+    /// # Important
+    ///
+    /// - The callback usually runs as a [`RundownGuard`] is dropped, so
+    ///   it must not wait for other references on the object.
+    ///
+    /// - Any number of callbacks can be registered, they run in the order
+    ///   they were registered in, after the waiting threads are woken.
+    ///
+    /// - Until the object is re-initialized, callbacks registered once
+    ///   rundown is complete run on the calling thread straight away,
+    ///   unless the callbacks registered before them have yet to run, in
+    ///   which case they run after them, on the thread running those.
+    ///
+    /// - The callbacks are kept in a global table, not in the object. If the
+    ///   object is dropped while references are still outstanding, which
+    ///   requires a guard to be leaked, the callbacks are dropped unrun.
+    ///
+    #[cfg(feature = "std")]
+    pub fn run_down_then<F>(&self, callback: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        // The callbacks flag only changes with the callbacks locked, so the
+        // thread which completes rundown with the flag set takes every
+        // callback registered for the rundown, and none registered for a
+        // later one.
+        let mut registered = callbacks::lock(self);
+        let mut current = self.load_flags();
+
+        let completed = loop {
+            if current.is_rundown_complete() {
+                // The thread which completed rundown has yet to take the
+                // callbacks registered before this one, which must run first.
+                if current.has_callbacks() {
+                    registered.push(Box::new(callback));
+                    return;
+                }
+
+                break None;
+            }
+
+            if current.is_ref_active() {
+                let bits_with_callbacks =
+                    to_flags(current.set_rundown_in_progress()).set_callbacks();
+
+                match self.compare_exchange(current.bits(), bits_with_callbacks) {
+                    Ok(_) => {
+                        registered.push(Box::new(callback));
+                        return;
+                    }
+                    Err(new_current) => current = to_flags(new_current),
+                }
+            } else {
+                // Without outstanding references this thread completes
+                // rundown, along with the callbacks of the rundown in
+                // progress if the last release didn't get to it yet.
+                match self.compare_exchange(current.bits(), current.start_rundown()) {
+                    Ok(_) => break Some(current),
+                    Err(new_current) => current = to_flags(new_current),
+                }
+            }
+        };
+
+        // Rundown may have been observed complete by a relaxed load, so
+        // synchronize with the releases of the references.
+        fence(Ordering::Acquire);
+
+        // Completing the rundown in progress makes this thread run the
+        // callbacks registered before this one, followed by this one.
+        if completed.is_some_and(RundownFlags::has_callbacks) {
+            registered.push(Box::new(callback));
+            drop(registered);
+
+            self.run_callbacks();
+            return;
+        }
+
+        drop(registered);
+        callback();
+    }
+
+    /// Like `wait_for_rundown`, but instead of using the [`WaitStrategy`], calls
+    /// `relax` repeatedly until there are no outstanding reference counts.
+    ///
+    /// This allows environments without the standard library to supply
+    /// their own means of waiting, such as yielding to a scheduler or
+    /// waiting for an interrupt. Passing `core::hint::spin_loop` spins.
+    ///
+    /// # Arguments
+    ///
+    /// * `relax` - Called each time rundown is observed to still be in progress.
+    ///
+    pub fn wait_for_rundown_with<F>(&self, mut relax: F)
+    where
+        F: FnMut(),
+    {
+        let mut current = self.mark_rundown_in_progress();
+
+        // Once complete the flags can only change through re-initialization,
+        // which also ends the rundown being waited for.
+        while current.state() == RundownState::RundownInProgress {
+            relax();
+            current = to_flags(self.ref_count.load(Ordering::Acquire));
+        }
+    }
+
+    /// Like `wait_for_rundown`, but gives up waiting once `timeout` has elapsed.
+    ///
+    /// Returns [`RundownWaitResult::Complete`] if rundown completed, otherwise
+    /// returns [`RundownWaitResult::TimedOut`] with the number of references
+    /// that were still outstanding.
+    ///
+    /// # Important
+    ///
+    /// - The object stays marked as 'rundown in progress' when the wait times
+    ///   out, so new protection requests will continue to fail.
+    ///
+    /// - The wait can be resumed by calling this method or `wait_for_rundown` again.
+    ///
+    #[cfg(feature = "std")]
+    pub fn wait_for_rundown_timeout(&self, timeout: Duration) -> RundownWaitResult {
+        self.begin_rundown().wait_timeout(timeout)
+    }
+
+    /// Like `wait_for_rundown`, but calls `progress` with the number of
+    /// references still outstanding each time `interval` elapses without
+    /// rundown completing, so slow rundowns can be reported.
+    ///
+    /// Returns the total time spent waiting for rundown to complete.
+    ///
+    /// # Arguments
+    ///
+    /// * `interval` - How long to wait between calls to `progress`. Intervals
+    ///   shorter than a millisecond, including zero, wait a millisecond, so
+    ///   the thread never spins calling `progress`.
+    ///
+    /// * `progress` - Called with the number of outstanding references.
+    ///
+    #[cfg(feature = "std")]
+    pub fn wait_for_rundown_with_progress<F>(&self, interval: Duration, mut progress: F) -> Duration
+    where
+        F: FnMut(u64),
+    {
+        let interval = interval.max(MIN_PROGRESS_INTERVAL);
+        let start = Instant::now();
+        let waiter = self.begin_rundown();
+
+        while let RundownWaitResult::TimedOut { outstanding } = waiter.wait_timeout(interval) {
+            progress(outstanding);
+        }
+
+        start.elapsed()
+    }
+
+    /// Returns the guards currently holding protection on this [`RundownRef`],
+    /// with the thread, time and backtrace of their acquisition, in the order
+    /// they were acquired. Guards split off another guard share its entry.
+    ///
+    /// Protection which was released by hand, after its guard was leaked,
+    /// is still listed.
+    #[cfg(feature = "track-holders")]
+    #[must_use]
+    pub fn holders(&self) -> Vec<RundownHolder> {
+        self.holders.holders()
+    }
+
+    /// Formats every guard currently holding protection on this
+    /// [`RundownRef`], as returned by `holders`, for diagnosing a rundown
+    /// which doesn't complete.
+    #[cfg(feature = "track-holders")]
+    #[must_use]
+    pub fn dump_holders(&self) -> String {
+        let holders = self.holders();
+        let mut dump = format!("{} holder(s) of {:?}\n", holders.len(), self);
+
+        for holder in holders {
+            dump.push_str(&holder.to_string());
+            dump.push('\n');
+        }
+
+        dump
+    }
+
+    /// Like `wait_for_rundown_timeout`, but if the wait times out, prints
+    /// the guards still holding protection, as formatted by `dump_holders`,
+    /// to the standard error stream.
+    #[cfg(feature = "track-holders")]
+    pub fn wait_for_rundown_or_dump(&self, timeout: Duration) -> RundownWaitResult {
+        let result = self.wait_for_rundown_timeout(timeout);
+
+        if let RundownWaitResult::TimedOut { .. } = result {
+            eprintln!(
+                "Rundown timed out after {:?}, {}",
+                timeout,
+                self.dump_holders()
+            );
+        }
+
+        result
+    }
+
+    /// Removes `count` references from the entry of a guard in the table
+    /// of holders, called as the guard releases its protection.
+    #[cfg(feature = "track-holders")]
+    pub(crate) fn untrack(&self, holder: HolderId, count: u64) {
+        self.holders.untrack(holder, count);
+    }
+
+    /// Blocks thread execution using the [`WaitStrategy`] until the rundown
+    /// in progress completes, for at most `timeout` if one is provided.
+    /// Returns false if the timeout elapsed first.
+    pub(crate) fn wait_for_completion(&self, timeout: Option<Duration>) -> bool {
+        let context = self.context();
+
+        !context.is_pending() || self.strategy.wait(&context, timeout)
+    }
+
+    /// Returns the outcome of waiting for the rundown in progress, as of a
+    /// single point in time, for a wait which gave up before it completed.
+    pub(crate) fn wait_result(&self) -> RundownWaitResult {
+        loop {
+            let current = to_flags(self.ref_count.load(Ordering::Acquire));
+
+            if current.state() != RundownState::RundownInProgress {
+                return RundownWaitResult::Complete;
+            }
+
+            if current.is_ref_active() {
+                return RundownWaitResult::TimedOut {
+                    outstanding: current.get_ref(),
+                };
+            }
+
+            // The last reference was released, and the releasing thread
+            // is about to mark rundown as complete.
+            spin_loop();
+        }
+    }
+
+    /// Turns on the rundown bit to inform all other threads that
+    /// rundown is currently in progress, along with the complete bit
+    /// if there are no outstanding references, and returns the resulting
+    /// flags.
+    #[inline]
+    fn mark_rundown_in_progress(&self) -> RundownFlags {
         let mut current = self.load_flags();
 
         loop {
             // If there are outstanding protection reference-counts
-            // then create the event. At this point it appears that
-            // other threads need to release their protection for
+            // then prepare the wait strategy. At this point it appears
+            // that other threads need to release their protection for
             // this thread to complete the rundown.
+            //
+            // The strategy must be prepared before the exchange, so that
+            // the thread releasing the last reference, which observes the
+            // exchange, is guaranteed to observe the prepared state as well.
             if current.is_ref_active() {
-                self.event
-                    .get_or_create(|| ManualResetEvent::new(State::Unset));
+                self.strategy.prepare();
             }
 
-            // Turn on the rundown bit to inform all other threads
-            // that rundown is currently in progress.
-            let bits_with_rundown = current.set_rundown_in_progress();
+            let bits_with_rundown = current.start_rundown();
 
             match self.compare_exchange(current.bits(), bits_with_rundown) {
                 Ok(_) => {
-                    current = to_flags(bits_with_rundown);
-                    break;
+                    let new_current = to_flags(bits_with_rundown);
+
+                    // Completing rundown before the thread which released the
+                    // last reference did, makes this thread run the callbacks.
+                    if current.has_callbacks()
+                        && !current.is_rundown_complete()
+                        && new_current.is_rundown_complete()
+                    {
+                        self.run_callbacks();
+                    }
+
+                    return new_current;
                 }
                 Err(new_current) => current = to_flags(new_current),
             }
         }
+    }
 
-        if current.is_ref_active() {
-            let event = self.event.get().expect("Must have been set");
-            event.wait();
+    /// Wakes everyone waiting for rundown to complete, called by the
+    /// thread which released the last outstanding reference.
+    #[inline]
+    fn signal_rundown_complete(&self) {
+        self.strategy.wake_all(&self.context());
+    }
+
+    /// Runs the callbacks registered with `run_down_then`, called by the thread
+    /// which completed rundown while the callbacks flag was set.
+    ///
+    /// The callbacks flag is only cleared once every callback has run, which
+    /// allows the object to be re-initialized. Until then, callbacks which
+    /// are registered are run after the others, to keep them in order.
+    #[cold]
+    #[cfg_attr(
+        not(feature = "std"),
+        allow(clippy::unused_self, clippy::missing_const_for_fn)
+    )]
+    fn run_callbacks(&self) {
+        /// Discards the remaining callbacks and clears the callbacks flag if
+        /// a callback panics, so the object can still be re-initialized.
+        #[cfg(feature = "std")]
+        struct Unwind<'a, W: WaitStrategy>(&'a RundownRef<W>);
+
+        #[cfg(feature = "std")]
+        impl<W: WaitStrategy> Drop for Unwind<'_, W> {
+            fn drop(&mut self) {
+                drop(self.0.take_callbacks());
+            }
         }
+
+        #[cfg(feature = "std")]
+        {
+            let _running = callbacks::enter(self);
+            let unwind = Unwind(self);
+
+            loop {
+                let mut registered = callbacks::lock(self);
+                let pending = registered.take();
+
+                if pending.is_empty() {
+                    // The flag is cleared with the callbacks locked, so any
+                    // callback registered afterwards runs straight away.
+                    self.clear_callbacks_flag();
+                    break;
+                }
+
+                drop(registered);
+
+                for callback in pending {
+                    callback();
+                }
+            }
+
+            core::mem::forget(unwind);
+        }
+    }
+
+    /// Removes the callbacks registered with `run_down_then`, and clears
+    /// the callbacks flag, which allows the object to be re-initialized.
+    #[cfg(feature = "std")]
+    fn take_callbacks(&self) -> Vec<Callback> {
+        let mut registered = callbacks::lock(self);
+
+        // The flag is cleared with the callbacks locked, so callbacks
+        // registered once the object is re-initialized aren't taken.
+        self.clear_callbacks_flag();
+
+        registered.take()
+    }
+
+    /// Clears the callbacks flag, publishing the effects of the callbacks
+    /// to the thread which re-initializes the object.
+    #[cfg(feature = "std")]
+    fn clear_callbacks_flag(&self) {
+        self.ref_count
+            .fetch_and(!RundownFlags::RUNDOWN_CALLBACKS.bits(), Ordering::Release);
+    }
+
+    /// Returns the context handed to the [`WaitStrategy`].
+    #[inline]
+    const fn context(&self) -> WaitContext<'_> {
+        WaitContext::new(&self.ref_count)
     }
 
     /// Load the current flags atomically, for use in the start of all
@@ -185,18 +1033,53 @@ impl RundownRef {
     /// Readability wrapper around atomic compare exchange.
     #[inline]
     fn compare_exchange(&self, current: u64, new: u64) -> Result<u64, u64> {
-        let success_ord = Ordering::Acquire;
+        // Releasing protection must publish the holder's accesses to the
+        // thread waiting for rundown, so successful exchanges both acquire
+        // and release.
+        let success_ord = Ordering::AcqRel;
         let failure_ord = Ordering::Relaxed;
         self.ref_count
             .compare_exchange(current, new, success_ord, failure_ord)
     }
 }
 
-#[cfg(test)]
+#[cfg(feature = "std")]
+impl<W: WaitStrategy> Drop for RundownRef<W> {
+    fn drop(&mut self) {
+        let current = self.load_flags();
+
+        // Callbacks are only left registered if references were leaked, they
+        // must not run once another object at the same address is run-down.
+        if current.has_callbacks() {
+            drop(self.take_callbacks());
+        }
+
+        // Likewise tasks are only left registered if their futures were
+        // leaked, they must not be woken by a rundown of another object.
+        #[cfg(all(feature = "async", target_os = "linux", not(feature = "loom")))]
+        if current.is_rundown_in_progress() && !current.is_rundown_complete() {
+            futex::discard_tasks(&self.ref_count);
+        }
+    }
+}
+
+impl<W: WaitStrategy> fmt::Debug for RundownRef<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let snapshot = self.snapshot();
+
+        f.debug_struct("RundownRef")
+            .field("state", &snapshot.state)
+            .field("outstanding_refs", &snapshot.outstanding_refs)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(all(test, not(feature = "loom")))]
 mod test {
     use super::RundownRef;
     use std::sync::Arc;
     use std::thread;
+    use std::time::Duration;
 
     //-------------------------------------------------------------------
     // Test: test_wait_when_protected
@@ -204,9 +1087,6 @@ mod test {
     // Description:
     //  Test that `wait_for_rundown` correctly run-down protection fails
     //
-    // Notes:
-    //  This test needs access to the reference count directly to work.
-    //
     #[test]
     #[allow(clippy::unwrap_used)]
     fn wait_when_protected() {
@@ -224,7 +1104,7 @@ mod test {
         // Spin until the rundown bit is set, one set we know
         // that the waiter is going to wait and the signal that
         // the drop below will send.
-        while rundown.load_flags().is_pre_rundown() {
+        while !rundown.is_rundown_in_progress() {
             thread::yield_now();
         }
 
@@ -237,4 +1117,73 @@ mod test {
         // TODO: Split out into an independent test.
         rundown.re_init();
     }
+
+    //-------------------------------------------------------------------
+    // Test: wait_with_relax_when_protected
+    //
+    // Description:
+    //  Test that `wait_for_rundown_with` calls the supplied relax
+    //  function until the outstanding reference is released.
+    //
+    #[test]
+    #[allow(clippy::unwrap_used)]
+    fn wait_with_relax_when_protected() {
+        let rundown = RundownRef::new();
+        let guard = rundown.try_acquire().unwrap();
+        let mut relax_count = 0;
+
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                std::mem::drop(guard);
+            });
+
+            rundown.wait_for_rundown_with(|| {
+                relax_count += 1;
+                thread::yield_now();
+            });
+        });
+
+        assert!(relax_count > 0);
+        assert!(rundown.try_acquire().is_err());
+        rundown.re_init();
+    }
+
+    //-------------------------------------------------------------------
+    // Test: failed_acquire_backs_out
+    //
+    // Description:
+    //  Test that acquisitions which fail because rundown is in progress
+    //  don't leave references behind, and that the last one to back out
+    //  completes rundown once the outstanding reference was released.
+    //
+    #[test]
+    #[allow(clippy::unwrap_used)]
+    fn failed_acquire_backs_out() {
+        let rundown = RundownRef::new();
+        let guard = rundown.try_acquire().unwrap();
+
+        thread::scope(|scope| {
+            scope.spawn(|| rundown.wait_for_rundown());
+
+            while !rundown.is_rundown_in_progress() {
+                thread::yield_now();
+            }
+
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..1000 {
+                        assert!(rundown.try_acquire_many(2).is_err());
+                    }
+                });
+            }
+
+            std::mem::drop(guard);
+        });
+
+        assert_eq!(rundown.outstanding_refs(), 0);
+        assert!(rundown.is_rundown_complete());
+        rundown.re_init();
+        assert!(rundown.try_acquire().is_ok());
+    }
 }
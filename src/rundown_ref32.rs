@@ -0,0 +1,504 @@
+// Copyright 2019 Brian Gianforcaro
+
+#[cfg(all(feature = "std", target_os = "linux", not(feature = "loom")))]
+use crate::futex;
+#[cfg(all(feature = "std", any(not(target_os = "linux"), feature = "loom")))]
+use crate::parking;
+#[cfg(feature = "std")]
+use crate::rundown_ref::RundownWaitResult;
+use crate::{
+    flags::RundownState, flags32::to_flags32, flags32::RundownFlags32, guard::RundownGuard32,
+    rundown_ref::RundownError, rundown_ref::RundownSnapshot, sync::spin_loop, sync::AtomicU32,
+};
+use core::{fmt, sync::atomic::Ordering, time::Duration};
+
+/// The largest number of references acquired with a single atomic increment.
+/// Larger counts are acquired with a compare and exchange loop instead, so
+/// increments which need to be backed out stay within the headroom above
+/// `RundownFlags32::MAX_REF_COUNT`, and never run into the flag bits.
+const MAX_SPECULATIVE_ACQUIRE: u32 = 1 << 8;
+
+/// A compact variant of [`crate::RundownRef`], for objects which are
+/// allocated in such numbers that the size of the run-down reference matters.
+///
+/// The reference count and flags are packed into a single `AtomicU32`, which
+/// limits the number of outstanding references to `MAX_REF_COUNT`. Waiting threads
+/// don't need an event of their own, on Linux they block on a futex on the
+/// reference count, elsewhere on an event from a global table keyed by the
+/// address of the object. Without the `std` feature waiting threads spin.
+pub struct RundownRef32 {
+    /// The reference count used to track the threads that currently have
+    /// outstanding run-down protection request being tracked by this object.
+    ///
+    /// The flags are held in the two most significant bits of the u32, and
+    /// the count in the remaining bits. The interpretation logic is
+    /// encapsulated in the `RundownFlags32` type.
+    ref_count: AtomicU32,
+}
+
+impl RundownRef32 {
+    /// The maximum number of references which can be outstanding at once.
+    pub const MAX_REF_COUNT: u32 = RundownFlags32::MAX_REF_COUNT;
+
+    const_fn! {
+        /// Initializes a new [`RundownRef32`], like [`crate::RundownRef::new`]
+        /// it can be used to initialize a `static`.
+        #[must_use]
+        pub fn new() -> Self {
+            Self {
+                ref_count: AtomicU32::new(0),
+            }
+        }
+    }
+
+    /// Re-initialize this instance so it can be used again. It is only valid
+    /// to call `re_init` once the object is completely run-down, via the
+    /// `wait_for_rundown` method.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the object has not been run-down, or if there are still
+    /// outstanding references on the object.
+    pub fn re_init(&self) {
+        if let Err(error) = self.try_re_init() {
+            panic!("Attempt to re-init before rundown is complete: {:?}", error);
+        }
+    }
+
+    /// Re-initialize this instance so it can be used again, like `re_init`,
+    /// but returns an error instead of panicking if rundown is not complete.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err(RundownError::NotRunDown)` if rundown has not been
+    /// started, or `Err(RundownError::ReferencesOutstanding)` if rundown is
+    /// in progress but there are still outstanding references on the object.
+    ///
+    pub fn try_re_init(&self) -> Result<(), RundownError> {
+        let mut current = self.load_flags();
+
+        loop {
+            match current.state() {
+                RundownState::Active => return Err(RundownError::NotRunDown),
+                RundownState::RundownInProgress => return Err(RundownError::ReferencesOutstanding),
+                RundownState::RundownComplete => {}
+            }
+
+            // Once complete, references are only held briefly by acquisitions
+            // which are about to back out, so wait for them to be released.
+            if current.is_ref_active() {
+                spin_loop();
+                current = self.load_flags();
+                continue;
+            }
+
+            match self.compare_exchange(current.bits(), 0) {
+                Ok(_) => return Ok(()),
+                Err(new_current) => current = to_flags32(new_current),
+            }
+        }
+    }
+
+    /// Returns the current state of run-down protection on this [`RundownRef32`].
+    #[inline]
+    #[must_use]
+    pub fn state(&self) -> RundownState {
+        to_flags32(self.ref_count.load(Ordering::Acquire)).state()
+    }
+
+    /// Returns the number of references currently outstanding on this
+    /// [`RundownRef32`], like [`crate::RundownRef::outstanding_refs`].
+    #[inline]
+    #[must_use]
+    pub fn outstanding_refs(&self) -> u32 {
+        to_flags32(self.ref_count.load(Ordering::Acquire)).get_ref()
+    }
+
+    /// Returns true if rundown has started, but there are still outstanding
+    /// references, so it has not completed yet.
+    #[inline]
+    #[must_use]
+    pub fn is_rundown_in_progress(&self) -> bool {
+        self.state() == RundownState::RundownInProgress
+    }
+
+    /// Returns true if rundown has completed, and the object has not
+    /// been re-initialized since.
+    #[inline]
+    #[must_use]
+    pub fn is_rundown_complete(&self) -> bool {
+        self.state() == RundownState::RundownComplete
+    }
+
+    /// Returns the state of run-down protection on this [`RundownRef32`]
+    /// and the number of outstanding references, as of a single point in time.
+    #[inline]
+    #[must_use]
+    pub fn snapshot(&self) -> RundownSnapshot {
+        let current = to_flags32(self.ref_count.load(Ordering::Acquire));
+
+        RundownSnapshot {
+            state: current.state(),
+            outstanding_refs: u64::from(current.get_ref()),
+        }
+    }
+
+    /// Attempts to acquire rundown protection on this [`RundownRef32`], returns
+    /// the [`RundownGuard32`] which holds the reference count, or returns an
+    /// error if the object is already being rundown.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err(RundownError::RundownInProgress)` if the rundown is
+    /// already in progress on the object, or `Err(RundownError::TooManyReferences)`
+    /// if `MAX_REF_COUNT` references are already outstanding.
+    ///
+    pub fn try_acquire(&self) -> Result<RundownGuard32<'_>, RundownError> {
+        self.acquire(1)?;
+        Ok(RundownGuard32::new(self, 1))
+    }
+
+    /// Attempts to acquire `count` instances of rundown protection on this
+    /// [`RundownRef32`] in a single atomic operation, returns the
+    /// [`RundownGuard32`] which holds all of the reference counts.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err(RundownError::RundownInProgress)` if the rundown is
+    /// already in progress on the object, or `Err(RundownError::TooManyReferences)`
+    /// if acquiring `count` references would exceed `MAX_REF_COUNT`.
+    ///
+    pub fn try_acquire_many(&self, count: u32) -> Result<RundownGuard32<'_>, RundownError> {
+        self.acquire(count)?;
+        Ok(RundownGuard32::new(self, count))
+    }
+
+    /// Acquires `count` reference counts on behalf of a guard, the guard
+    /// is responsible for releasing them when it goes out of scope.
+    fn acquire(&self, count: u32) -> Result<(), RundownError> {
+        if count > MAX_SPECULATIVE_ACQUIRE {
+            return self.acquire_exact(count);
+        }
+
+        let previous = to_flags32(self.ref_count.fetch_add(count, Ordering::Acquire));
+
+        if previous.is_rundown_in_progress() {
+            self.release_held(count);
+            return Err(RundownError::RundownInProgress);
+        }
+
+        if previous.get_ref() > RundownFlags32::MAX_REF_COUNT - count {
+            self.release_held(count);
+            return Err(RundownError::TooManyReferences);
+        }
+
+        Ok(())
+    }
+
+    /// Acquires `count` reference counts on behalf of a guard with a compare
+    /// and exchange loop, which never changes the count once rundown started.
+    #[cold]
+    fn acquire_exact(&self, count: u32) -> Result<(), RundownError> {
+        let mut current = self.load_flags();
+
+        loop {
+            if current.is_rundown_in_progress() {
+                return Err(RundownError::RundownInProgress);
+            }
+
+            let Some(new_bits_with_ref) = current.checked_add_refs(count) else {
+                return Err(RundownError::TooManyReferences);
+            };
+
+            match self.compare_exchange(current.bits(), new_bits_with_ref) {
+                Ok(_) => return Ok(()),
+                Err(new_current) => current = to_flags32(new_current),
+            }
+        }
+    }
+
+    /// Release previously acquired rundown protection.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no references are outstanding.
+    ///
+    pub fn release(&self) {
+        self.release_many(1);
+    }
+
+    /// Release previously acquired rundown protection, like `release`, but
+    /// returns an error instead of panicking if no references are outstanding.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err(RundownError::ReferenceUnderflow)` if no references
+    /// are outstanding, the reference count is left unchanged.
+    ///
+    pub fn try_release(&self) -> Result<(), RundownError> {
+        self.try_release_many(1)
+    }
+
+    /// Release `count` instances of previously acquired rundown protection
+    /// in a single atomic operation.
+    ///
+    /// # Panics
+    ///
+    /// Panics if fewer than `count` references are outstanding.
+    ///
+    pub fn release_many(&self, count: u32) {
+        assert!(
+            self.try_release_many(count).is_ok(),
+            "Decrementing the reference-count would have under-flowed!"
+        );
+    }
+
+    /// Release `count` references which are known to be held, by a guard or
+    /// by an acquisition which backs out, with a single atomic decrement.
+    pub(crate) fn release_held(&self, count: u32) {
+        let previous = to_flags32(self.ref_count.fetch_sub(count, Ordering::Release));
+
+        // Releasing the last reference while rundown is in progress
+        // is followed by marking rundown as complete.
+        let current = to_flags32(previous.dec_refs(count));
+        if current.is_ref_zero()
+            && current.is_rundown_in_progress()
+            && !current.is_rundown_complete()
+        {
+            self.complete_rundown(current);
+        }
+    }
+
+    /// Release `count` instances of previously acquired rundown protection,
+    /// like `release_many`, but returns an error instead of panicking if
+    /// fewer than `count` references are outstanding.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err(RundownError::ReferenceUnderflow)` if fewer than
+    /// `count` references are outstanding, the reference count is left unchanged.
+    ///
+    pub fn try_release_many(&self, count: u32) -> Result<(), RundownError> {
+        let mut current = self.load_flags();
+
+        loop {
+            let Some(bits_with_decrement) = current.checked_dec_refs(count) else {
+                return Err(RundownError::ReferenceUnderflow);
+            };
+
+            match self.compare_exchange(current.bits(), bits_with_decrement) {
+                Ok(_) => {
+                    current = to_flags32(bits_with_decrement);
+                    break;
+                }
+                Err(new_current) => current = to_flags32(new_current),
+            }
+        }
+
+        if current.is_ref_zero()
+            && current.is_rundown_in_progress()
+            && !current.is_rundown_complete()
+        {
+            self.complete_rundown(current);
+        }
+
+        Ok(())
+    }
+
+    /// Marks rundown as complete after the last reference was released while
+    /// rundown was in progress, and wakes everyone waiting for rundown.
+    #[cold]
+    fn complete_rundown(&self, mut current: RundownFlags32) {
+        loop {
+            // Another thread completed rundown in the meantime, without
+            // waking the waiters, and may even have re-initialized it since.
+            if current.is_pre_rundown() || current.is_rundown_complete() {
+                break;
+            }
+
+            // An acquisition which is about to back out took a reference in
+            // the meantime, releasing it completes rundown instead.
+            if current.is_ref_active() {
+                return;
+            }
+
+            match self.compare_exchange(current.bits(), current.set_rundown_complete()) {
+                Ok(_) => break,
+                Err(new_current) => current = to_flags32(new_current),
+            }
+        }
+
+        self.signal_rundown_complete();
+    }
+
+    /// Blocks thread execution until there are no outstanding reference
+    /// counts taken on the [`RundownRef32`], and the internal representation
+    /// has been marked with as 'rundown in progress' to signal
+    /// that no other thread can safely acquire a reference count afterwards.
+    ///
+    /// Like [`crate::RundownRef::wait_for_rundown`], any number of threads
+    /// can wait concurrently, and the method is idempotent.
+    pub fn wait_for_rundown(&self) {
+        let current = self.mark_rundown_in_progress();
+
+        if !current.is_rundown_complete() {
+            self.wait(None);
+        }
+    }
+
+    /// Like `wait_for_rundown`, but calls `relax` repeatedly until there
+    /// are no outstanding reference counts, instead of blocking.
+    ///
+    /// # Arguments
+    ///
+    /// * `relax` - Called each time rundown is observed to still be in progress.
+    ///
+    pub fn wait_for_rundown_with<F>(&self, mut relax: F)
+    where
+        F: FnMut(),
+    {
+        self.mark_rundown_in_progress();
+
+        while self.is_pending() {
+            relax();
+        }
+    }
+
+    /// Like `wait_for_rundown`, but gives up waiting once `timeout` has elapsed.
+    ///
+    /// Returns [`RundownWaitResult::Complete`] if rundown completed, otherwise
+    /// returns [`RundownWaitResult::TimedOut`] with the number of references
+    /// that were still outstanding. The object stays marked as 'rundown in
+    /// progress' when the wait times out.
+    #[cfg(feature = "std")]
+    pub fn wait_for_rundown_timeout(&self, timeout: Duration) -> RundownWaitResult {
+        let current = self.mark_rundown_in_progress();
+
+        if current.is_rundown_complete() || self.wait(Some(timeout)) {
+            return RundownWaitResult::Complete;
+        }
+
+        // The last reference may have been released since the wait timed
+        // out, which is reported as complete, like `RundownWaiter::wait_timeout`.
+        loop {
+            let current = to_flags32(self.ref_count.load(Ordering::Acquire));
+
+            if current.state() != RundownState::RundownInProgress {
+                return RundownWaitResult::Complete;
+            }
+
+            if current.is_ref_active() {
+                return RundownWaitResult::TimedOut {
+                    outstanding: u64::from(current.get_ref()),
+                };
+            }
+
+            // The releasing thread is about to mark rundown as complete.
+            spin_loop();
+        }
+    }
+
+    /// Turns on the rundown bit to inform all other threads that
+    /// rundown is currently in progress, along with the complete bit
+    /// if there are no outstanding references, and returns the resulting
+    /// flags.
+    fn mark_rundown_in_progress(&self) -> RundownFlags32 {
+        let mut current = self.load_flags();
+
+        loop {
+            let bits_with_rundown = current.start_rundown();
+
+            match self.compare_exchange(current.bits(), bits_with_rundown) {
+                Ok(_) => return to_flags32(bits_with_rundown),
+                Err(new_current) => current = to_flags32(new_current),
+            }
+        }
+    }
+
+    /// Returns true while rundown is in progress but not yet complete.
+    #[inline]
+    fn is_pending(&self) -> bool {
+        self.state() == RundownState::RundownInProgress
+    }
+
+    /// Blocks on the futex on the reference count while rundown is pending,
+    /// for at most `timeout`. Returns false if the timeout elapsed first.
+    #[cfg(all(feature = "std", target_os = "linux", not(feature = "loom")))]
+    fn wait(&self, timeout: Option<Duration>) -> bool {
+        futex::wait_while(&self.ref_count, timeout, |bits| {
+            to_flags32(bits).state() == RundownState::RundownInProgress
+        })
+    }
+
+    /// Blocks on the event this object shares with others in the global table
+    /// while rundown is pending, for at most `timeout`. Returns false if the
+    /// timeout elapsed first.
+    #[cfg(all(feature = "std", any(not(target_os = "linux"), feature = "loom")))]
+    fn wait(&self, timeout: Option<Duration>) -> bool {
+        let event = parking::event_for(self);
+
+        timeout.map_or_else(
+            || {
+                event.wait_while(|| self.is_pending());
+                true
+            },
+            |timeout| event.wait_while_timeout(timeout, || self.is_pending()),
+        )
+    }
+
+    /// Spins while rundown is pending, there is no way to block the thread.
+    #[cfg(not(feature = "std"))]
+    fn wait(&self, _timeout: Option<Duration>) -> bool {
+        while self.is_pending() {
+            spin_loop();
+        }
+
+        true
+    }
+
+    /// Wakes everyone waiting for rundown to complete, called by the
+    /// thread which released the last outstanding reference.
+    #[inline]
+    #[cfg_attr(
+        not(feature = "std"),
+        allow(clippy::unused_self, clippy::missing_const_for_fn)
+    )]
+    fn signal_rundown_complete(&self) {
+        #[cfg(all(feature = "std", target_os = "linux", not(feature = "loom")))]
+        futex::wake_all(&self.ref_count);
+
+        #[cfg(all(feature = "std", any(not(target_os = "linux"), feature = "loom")))]
+        parking::event_for(self).set();
+    }
+
+    /// Load the current flags atomically, for use in the start of all
+    /// atomic compare and exchange loops in this implementation.
+    #[inline]
+    fn load_flags(&self) -> RundownFlags32 {
+        to_flags32(self.ref_count.load(Ordering::Relaxed))
+    }
+
+    /// Readability wrapper around atomic compare exchange.
+    #[inline]
+    fn compare_exchange(&self, current: u32, new: u32) -> Result<u32, u32> {
+        self.ref_count
+            .compare_exchange(current, new, Ordering::AcqRel, Ordering::Relaxed)
+    }
+}
+
+impl Default for RundownRef32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for RundownRef32 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let snapshot = self.snapshot();
+
+        f.debug_struct("RundownRef32")
+            .field("state", &snapshot.state)
+            .field("outstanding_refs", &snapshot.outstanding_refs)
+            .finish()
+    }
+}
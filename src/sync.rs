@@ -0,0 +1,21 @@
+// Copyright 2019 Brian Gianforcaro
+
+//! The synchronization primitives used by the crate. When the `loom` feature
+//! is enabled they are swapped for their `loom` counterparts, so the model
+//! checking tests can explore every interleaving of the operations on them.
+//! Without the `std` feature only the atomics from `core` are available.
+
+#[cfg(not(feature = "loom"))]
+pub use core::{
+    hint::spin_loop, sync::atomic::fence, sync::atomic::AtomicBool, sync::atomic::AtomicPtr,
+    sync::atomic::AtomicU32, sync::atomic::AtomicU64, sync::atomic::AtomicUsize,
+};
+#[cfg(feature = "loom")]
+pub use loom::hint::spin_loop;
+#[cfg(feature = "loom")]
+pub use loom::sync::{
+    atomic::fence, atomic::AtomicBool, atomic::AtomicPtr, atomic::AtomicU32, atomic::AtomicU64,
+    atomic::AtomicUsize, Condvar, Mutex, MutexGuard,
+};
+#[cfg(all(feature = "std", not(feature = "loom")))]
+pub use std::sync::{Condvar, Mutex, MutexGuard};
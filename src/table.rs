@@ -0,0 +1,141 @@
+// Copyright 2019 Brian Gianforcaro
+
+//! Global tables keyed by the address of an object, which hold the state of
+//! objects too small to hold it themselves, such as events, wakers and
+//! callbacks.
+//!
+//! Every table has the same fixed number of buckets, and objects whose
+//! addresses map to the same bucket share it, so the state in a bucket is
+//! tagged with the key of the object it belongs to.
+
+use crate::sync::{Mutex, MutexGuard};
+use core::mem;
+use std::{ptr, sync::PoisonError};
+
+/// The number of buckets in every table.
+pub const BUCKETS: usize = 64;
+
+/// Declares a global table, of `BUCKETS` buckets each initialized to `init`.
+///
+/// The loom primitives can't be constructed in a const context, and must be
+/// created again for every execution of the model, so under loom the table
+/// is created lazily instead.
+macro_rules! address_table {
+    ($(#[$attr:meta])* static $name:ident: $ty:ty = $init:expr;) => {
+        $(#[$attr])*
+        #[cfg(not(feature = "loom"))]
+        static $name: [$ty; $crate::table::BUCKETS] = {
+            #[allow(clippy::declare_interior_mutable_const)]
+            const EMPTY: $ty = $init;
+            [EMPTY; $crate::table::BUCKETS]
+        };
+
+        #[cfg(feature = "loom")]
+        loom::lazy_static! {
+            $(#[$attr])*
+            static ref $name: Vec<$ty> =
+                (0..$crate::table::BUCKETS).map(|_| $init).collect();
+        }
+    };
+}
+
+pub(crate) use address_table;
+
+/// The key of an object in a table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Key {
+    /// The address of the object.
+    address: usize,
+
+    /// The index of the bucket the object maps to.
+    bucket: usize,
+}
+
+impl Key {
+    /// Returns the key of `object`.
+    pub fn of<T>(object: &T) -> Self {
+        let address = ptr::from_ref(object) as usize;
+
+        // The low bits of the address are always zero for an aligned
+        // object, so they don't help to distribute them across the buckets.
+        Self {
+            address,
+            bucket: (address / mem::align_of::<T>()) % BUCKETS,
+        }
+    }
+
+    /// Returns the bucket of `table` the object maps to.
+    pub fn bucket<B>(self, table: &'static [B]) -> &'static B {
+        &table[self.bucket]
+    }
+
+    /// Locks the bucket of `table` the object maps to. The buckets are
+    /// always left in a consistent state, so poisoning is ignored.
+    pub fn lock<B>(self, table: &'static [Mutex<B>]) -> MutexGuard<'static, B> {
+        self.bucket(table)
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// The values held for the objects whose keys share a bucket, in the order
+/// they were inserted. Each value is identified, so its holder can find it
+/// again.
+pub struct Entries<V> {
+    /// The identifier handed to the next value inserted in the bucket.
+    next_id: u64,
+
+    /// The values, along with the key of their object and their identifier.
+    entries: Vec<(Key, u64, V)>,
+}
+
+impl<V> Entries<V> {
+    /// Creates an empty bucket.
+    pub const fn new() -> Self {
+        Self {
+            next_id: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Inserts `value` for the object of `key`, and returns its identifier.
+    pub fn insert(&mut self, key: Key, value: V) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.push((key, id, value));
+        id
+    }
+
+    /// Returns the value identified by `id`, unless it was removed.
+    #[cfg(all(feature = "async", target_os = "linux", not(feature = "loom")))]
+    pub fn get_mut(&mut self, id: u64) -> Option<&mut V> {
+        self.entries
+            .iter_mut()
+            .find(|entry| entry.1 == id)
+            .map(|entry| &mut entry.2)
+    }
+
+    /// Removes and returns the value identified by `id`, unless it was
+    /// already removed.
+    pub fn remove(&mut self, id: u64) -> Option<V> {
+        let index = self.entries.iter().position(|entry| entry.1 == id)?;
+        Some(self.entries.remove(index).2)
+    }
+
+    /// Removes and returns all of the values of the object of `key`, in the
+    /// order they were inserted.
+    pub fn take(&mut self, key: Key) -> Vec<V> {
+        let mut taken = Vec::new();
+        let mut index = 0;
+
+        while index < self.entries.len() {
+            if self.entries[index].0 == key {
+                taken.push(self.entries.remove(index).2);
+            } else {
+                index += 1;
+            }
+        }
+
+        taken
+    }
+}
@@ -0,0 +1,385 @@
+// Copyright 2019 Brian Gianforcaro
+
+use crate::{
+    flags::RundownState, guard::RundownGuard, rundown_ref::RundownError, rundown_ref::RundownRef,
+    sync::spin_loop, sync::AtomicPtr, sync::AtomicU64, sync::AtomicUsize,
+    wait::DefaultWaitStrategy, wait::WaitStrategy, waiter::RundownWaiter,
+};
+use core::{fmt, ptr, slice, str, sync::atomic::Ordering};
+
+/// The number of distinct tags a [`TaggedRundownRef`] can count.
+const MAX_TAGS: usize = 8;
+
+/// The length of the name in a [`TagSlot`] which was claimed, but whose
+/// name has not been published yet.
+const UNPUBLISHED: usize = usize::MAX;
+
+/// Identifies the subsystem acquiring run-down protection on a
+/// [`TaggedRundownRef`], for accounting of the outstanding references.
+///
+/// The trait is implemented for static strings, and can be implemented
+/// for small enums by naming each of their variants:
+///
+/// ```rust
+/// use run_down::RundownTag;
+///
+/// #[derive(Clone, Copy)]
+/// enum Subsystem {
+///     Http,
+///     Cron,
+/// }
+///
+/// impl RundownTag for Subsystem {
+///     fn name(self) -> &'static str {
+///         match self {
+///             Self::Http => "http",
+///             Self::Cron => "cron",
+///         }
+///     }
+/// }
+/// ```
+pub trait RundownTag: Copy {
+    /// Returns the name of the tag, tags with the same name share a count.
+    fn name(self) -> &'static str;
+}
+
+impl RundownTag for &'static str {
+    #[inline]
+    fn name(self) -> &'static str {
+        self
+    }
+}
+
+/// The count of the references outstanding with a tag. The slot is
+/// claimed by the first successful acquisition with the tag, and never
+/// released.
+struct TagSlot {
+    /// The address of the name of the tag, null until the slot is claimed.
+    name_ptr: AtomicPtr<u8>,
+
+    /// The length of the name of the tag, `UNPUBLISHED` until the
+    /// thread which claimed the slot stores it.
+    name_len: AtomicUsize,
+
+    /// The number of references outstanding with the tag.
+    outstanding: AtomicU64,
+}
+
+impl TagSlot {
+    const_fn! {
+        /// Creates a slot which has not been claimed by any tag.
+        fn new() -> Self {
+            Self {
+                name_ptr: AtomicPtr::new(ptr::null_mut()),
+                name_len: AtomicUsize::new(UNPUBLISHED),
+                outstanding: AtomicU64::new(0),
+            }
+        }
+    }
+
+    /// Returns the name of the tag counted in the slot, or `None` if
+    /// the slot has not been claimed.
+    fn name(&self) -> Option<&'static str> {
+        let name_ptr = self.name_ptr.load(Ordering::Acquire);
+
+        if name_ptr.is_null() {
+            return None;
+        }
+
+        // The thread which claimed the slot publishes the length straight
+        // after the address, so it is only briefly unavailable.
+        let name_len = loop {
+            let name_len = self.name_len.load(Ordering::Acquire);
+
+            if name_len != UNPUBLISHED {
+                break name_len;
+            }
+
+            spin_loop();
+        };
+
+        // Safety: The address and length were both taken from the same
+        // `&'static str` by the thread which claimed the slot.
+        Some(unsafe { str::from_utf8_unchecked(slice::from_raw_parts(name_ptr, name_len)) })
+    }
+
+    /// Attempts to claim the slot for the tag `name`, returns false if
+    /// another tag claimed the slot first.
+    fn claim(&self, name: &'static str) -> bool {
+        let claimed = self
+            .name_ptr
+            .compare_exchange(
+                ptr::null_mut(),
+                name.as_ptr().cast_mut(),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .is_ok();
+
+        if claimed {
+            self.name_len.store(name.len(), Ordering::Release);
+        }
+
+        claimed
+    }
+}
+
+/// Returns the name of the tag counted in each claimed slot, along with the
+/// number of references outstanding with it.
+fn outstanding_by_tag(slots: &[TagSlot]) -> impl Iterator<Item = (&'static str, u64)> + '_ {
+    // Slots are claimed in order, so the claimed slots come first.
+    slots.iter().map_while(|slot| {
+        slot.name()
+            .map(|name| (name, slot.outstanding.load(Ordering::Relaxed)))
+    })
+}
+
+/// Returns the slots of a [`TaggedRundownRef`], none of which are claimed.
+#[cfg(not(feature = "loom"))]
+const fn new_slots() -> [TagSlot; MAX_TAGS] {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: TagSlot = TagSlot::new();
+    [EMPTY; MAX_TAGS]
+}
+
+/// Returns the slots of a [`TaggedRundownRef`], none of which are claimed.
+#[cfg(feature = "loom")]
+fn new_slots() -> [TagSlot; MAX_TAGS] {
+    core::array::from_fn(|_| TagSlot::new())
+}
+
+/// A [`RundownRef`] which counts the outstanding references acquired
+/// with each [`RundownTag`].
+///
+/// This shows which subsystems are still holding up a slow rundown. The
+/// counts are kept in a fixed array of atomics next to the reference count,
+/// so up to `MAX_TAGS` distinct tags can be counted, and acquiring
+/// protection costs one more atomic increment.
+///
+/// # Example
+///
+/// ```rust
+/// use run_down::TaggedRundownRef;
+///
+/// let rundown = TaggedRundownRef::new();
+/// let http = rundown.try_acquire_tagged("http").unwrap();
+/// let _cron = rundown.try_acquire_tagged("cron").unwrap();
+/// drop(http);
+///
+/// let outstanding: Vec<_> = rundown.outstanding_by_tag().collect();
+/// assert_eq!(vec![("http", 0), ("cron", 1)], outstanding);
+/// ```
+pub struct TaggedRundownRef<W: WaitStrategy = DefaultWaitStrategy> {
+    /// The run-down reference counting all of the outstanding references.
+    protection: RundownRef<W>,
+
+    /// The counts of the outstanding references with each tag, in the
+    /// order the tags were first used.
+    slots: [TagSlot; MAX_TAGS],
+}
+
+impl TaggedRundownRef {
+    const_fn! {
+        /// Initializes a new [`TaggedRundownRef`], which uses the default
+        /// [`WaitStrategy`].
+        #[inline]
+        #[must_use]
+        pub fn new() -> Self {
+            Self::with_wait_strategy(DefaultWaitStrategy::new())
+        }
+    }
+}
+
+impl<W: WaitStrategy> TaggedRundownRef<W> {
+    /// The maximum number of distinct tags which can be counted.
+    pub const MAX_TAGS: usize = MAX_TAGS;
+
+    const_fn! {
+        /// Initializes a new [`TaggedRundownRef`], which waits for rundown
+        /// using the [`WaitStrategy`] provided.
+        pub fn with_wait_strategy(strategy: W) -> Self {
+            Self {
+                protection: RundownRef::with_wait_strategy(strategy),
+                slots: new_slots(),
+            }
+        }
+    }
+
+    /// Attempts to acquire rundown protection on behalf of `tag`, returns
+    /// the [`TaggedRundownGuard`] which holds the reference count, or returns
+    /// an error if the object is already being rundown.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err(RundownError::TooManyTags)` if `MAX_TAGS` other tags
+    /// are already counted, otherwise the errors returned by `try_acquire`
+    /// on [`RundownRef`].
+    ///
+    pub fn try_acquire_tagged<T>(&self, tag: T) -> Result<TaggedRundownGuard<'_, W>, RundownError>
+    where
+        T: RundownTag,
+    {
+        let name = tag.name();
+
+        // Protection is acquired first, so a tag is only given a slot once
+        // it holds a reference, and failed attempts can't use up the slots.
+        let guard = self.protection.try_acquire()?;
+        let slot = self.slot_for(name).ok_or(RundownError::TooManyTags)?;
+
+        // The counts are only reported, they don't order any other accesses.
+        slot.outstanding.fetch_add(1, Ordering::Relaxed);
+
+        Ok(TaggedRundownGuard {
+            name,
+            outstanding: &slot.outstanding,
+            _guard: guard,
+        })
+    }
+
+    /// Returns the name of each tag protection has been acquired with, along
+    /// with the number of references outstanding with it, in the order the
+    /// tags were first used. Tags which no longer hold references are
+    /// reported with a count of zero.
+    ///
+    /// Each count is read separately, so they may not add up to the total
+    /// returned by `outstanding_refs` while protection is acquired and released.
+    pub fn outstanding_by_tag(&self) -> impl Iterator<Item = (&'static str, u64)> + '_ {
+        outstanding_by_tag(&self.slots)
+    }
+
+    /// Returns the total number of references currently outstanding.
+    #[inline]
+    #[must_use]
+    pub fn outstanding_refs(&self) -> u64 {
+        self.protection.outstanding_refs()
+    }
+
+    /// Returns the current state of run-down protection on the object.
+    #[inline]
+    #[must_use]
+    pub fn state(&self) -> RundownState {
+        self.protection.state()
+    }
+
+    /// Blocks thread execution until there are no outstanding references,
+    /// like `wait_for_rundown` on [`RundownRef`].
+    pub fn wait_for_rundown(&self) {
+        self.protection.wait_for_rundown();
+    }
+
+    /// Marks the object as 'rundown in progress' without waiting, like
+    /// `begin_rundown` on [`RundownRef`].
+    pub fn begin_rundown(&self) -> RundownWaiter<'_, W> {
+        self.protection.begin_rundown()
+    }
+
+    /// Re-initialize this instance so it can be used again, like `re_init`
+    /// on [`RundownRef`]. The tags which were counted stay claimed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the object has not been run-down, or if there are still
+    /// outstanding references on the object.
+    pub fn re_init(&self) {
+        self.protection.re_init();
+    }
+
+    /// Returns the [`RundownRef`] counting all of the outstanding references,
+    /// for the rest of its API. Protection acquired on it directly isn't
+    /// counted against any tag.
+    #[inline]
+    #[must_use]
+    pub const fn as_rundown_ref(&self) -> &RundownRef<W> {
+        &self.protection
+    }
+
+    /// Returns the slot counting the tag `name`, claiming an unused slot if
+    /// the tag has not been counted yet, or `None` if all slots are in use.
+    fn slot_for(&self, name: &'static str) -> Option<&TagSlot> {
+        // Slots are claimed in order, and a slot is only skipped once it
+        // holds another tag, so each tag is counted in a single slot.
+        for slot in &self.slots {
+            loop {
+                match slot.name() {
+                    Some(slot_name) if slot_name == name => return Some(slot),
+                    Some(_) => break,
+                    None => {
+                        if slot.claim(name) {
+                            return Some(slot);
+                        }
+                    }
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl<W: WaitStrategy + Default> Default for TaggedRundownRef<W> {
+    fn default() -> Self {
+        Self::with_wait_strategy(W::default())
+    }
+}
+
+impl<W: WaitStrategy> fmt::Debug for TaggedRundownRef<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaggedRundownRef")
+            .field("protection", &self.protection)
+            .field("outstanding_by_tag", &OutstandingByTag(&self.slots))
+            .finish()
+    }
+}
+
+/// Formats the outstanding references with each tag as a map.
+struct OutstandingByTag<'a>(&'a [TagSlot]);
+
+impl fmt::Debug for OutstandingByTag<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(outstanding_by_tag(self.0)).finish()
+    }
+}
+
+/// An RAII guard holding run-down protection on a [`TaggedRundownRef`].
+///
+/// The protection is counted against the tag it was acquired with until
+/// the guard is dropped (falls out of scope), and the protection released.
+///
+/// This structure is created by the `try_acquire_tagged` method on
+/// `TaggedRundownRef`.
+pub struct TaggedRundownGuard<'r, W: WaitStrategy = DefaultWaitStrategy> {
+    /// The name of the tag the protection was acquired with.
+    name: &'static str,
+
+    /// The count of the references outstanding with the tag.
+    outstanding: &'r AtomicU64,
+
+    /// The run-down protection held on behalf of this guard, released
+    /// once the count of the tag is decremented.
+    _guard: RundownGuard<'r, W>,
+}
+
+impl<W: WaitStrategy> TaggedRundownGuard<'_, W> {
+    /// Returns the name of the tag the protection was acquired with.
+    #[inline]
+    #[must_use]
+    pub const fn tag(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl<W: WaitStrategy> Drop for TaggedRundownGuard<'_, W> {
+    /// Removes the protection from the count of the tag, before the
+    /// protection itself is released.
+    fn drop(&mut self) {
+        self.outstanding.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl<W: WaitStrategy> fmt::Debug for TaggedRundownGuard<'_, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaggedRundownGuard")
+            .field("tag", &self.name)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,390 @@
+// Copyright 2019 Brian Gianforcaro
+
+#[cfg(all(feature = "std", any(not(target_os = "linux"), feature = "loom")))]
+use crate::event::LazyEvent;
+#[cfg(all(feature = "std", target_os = "linux", not(feature = "loom")))]
+use crate::futex;
+use crate::{flags::to_flags, flags::RundownState, sync::AtomicU64};
+use core::{sync::atomic::Ordering, time::Duration};
+#[cfg(feature = "async")]
+use std::task::Waker;
+#[cfg(feature = "std")]
+use std::{
+    sync::Mutex, sync::MutexGuard, sync::PoisonError, thread, thread::Thread, time::Instant,
+};
+
+/// Decides how a thread waiting for rundown blocks until rundown completes,
+/// and how the thread which completes rundown wakes it.
+///
+/// A [`crate::RundownRef`] embeds an instance of its strategy, so a strategy
+/// can keep state for each object, such as the threads which are waiting.
+pub trait WaitStrategy {
+    /// Called by a thread which is about to wait, before it marks rundown as
+    /// in progress, if references are outstanding.
+    ///
+    /// The thread which completes rundown is only guaranteed to observe state
+    /// created before rundown was marked as in progress, so strategies which
+    /// lazily create the state used to wake waiters must create it here.
+    #[inline]
+    fn prepare(&self) {}
+
+    /// Blocks the calling thread while `context.is_pending()` returns true,
+    /// for at most `timeout` if one is provided. Returns true once rundown is
+    /// no longer pending, or false if the timeout elapsed first.
+    fn wait(&self, context: &WaitContext<'_>, timeout: Option<Duration>) -> bool;
+
+    /// Wakes all threads blocked in `wait`. Called by the thread which
+    /// completes rundown, once `is_pending` returns false.
+    fn wake_all(&self, context: &WaitContext<'_>);
+}
+
+/// The rundown a [`WaitStrategy`] is waiting for.
+pub struct WaitContext<'a> {
+    /// The reference count of the object being run-down.
+    ref_count: &'a AtomicU64,
+}
+
+impl<'a> WaitContext<'a> {
+    /// Creates the context for waiting on the reference count provided.
+    pub(crate) const fn new(ref_count: &'a AtomicU64) -> Self {
+        Self { ref_count }
+    }
+
+    /// Returns true while rundown is in progress but not yet complete. Once
+    /// this returns false waiters must stop waiting, even if the object has
+    /// since been re-initialized, as the rundown they waited for completed.
+    #[inline]
+    #[must_use]
+    pub fn is_pending(&self) -> bool {
+        to_flags(self.ref_count.load(Ordering::Acquire)).state() == RundownState::RundownInProgress
+    }
+}
+
+/// The [`WaitStrategy`] used when none is specified, [`EventWait`] with the
+/// `std` feature, and [`SpinWait`] without it.
+#[cfg(feature = "std")]
+pub type DefaultWaitStrategy = EventWait;
+
+/// The [`WaitStrategy`] used when none is specified, `EventWait` with the
+/// `std` feature, and [`SpinWait`] without it.
+#[cfg(not(feature = "std"))]
+pub type DefaultWaitStrategy = SpinWait;
+
+/// The default [`WaitStrategy`], which blocks waiting threads until they are
+/// woken by the thread which completes rundown.
+///
+/// On Linux threads block on a futex on the reference count itself, so the
+/// strategy has no state. Elsewhere they block on an event, which is only
+/// allocated if references are outstanding when rundown starts.
+#[cfg(feature = "std")]
+#[derive(Default)]
+pub struct EventWait {
+    /// The event used to signal the threads waiting for rundown that
+    /// rundown is now complete.
+    #[cfg(any(not(target_os = "linux"), feature = "loom"))]
+    event: LazyEvent,
+}
+
+#[cfg(feature = "std")]
+impl EventWait {
+    const_fn! {
+        /// Creates an [`EventWait`], the event is only created once a
+        /// thread has to wait.
+        #[must_use]
+        pub fn new() -> Self {
+            Self {
+                #[cfg(any(not(target_os = "linux"), feature = "loom"))]
+                event: LazyEvent::new(),
+            }
+        }
+    }
+}
+
+#[cfg(all(feature = "std", target_os = "linux", not(feature = "loom")))]
+impl WaitStrategy for EventWait {
+    fn wait(&self, context: &WaitContext<'_>, timeout: Option<Duration>) -> bool {
+        // Completing rundown sets the complete flag, in the half of the
+        // word the futex waits on, before the waiters are woken.
+        futex::wait_while(context.ref_count, timeout, |bits| {
+            to_flags(bits).state() == RundownState::RundownInProgress
+        })
+    }
+
+    fn wake_all(&self, context: &WaitContext<'_>) {
+        futex::wake_all(context.ref_count);
+
+        #[cfg(feature = "async")]
+        futex::wake_tasks(context.ref_count);
+    }
+}
+
+#[cfg(all(feature = "std", any(not(target_os = "linux"), feature = "loom")))]
+impl WaitStrategy for EventWait {
+    fn prepare(&self) {
+        self.event.get_or_create();
+    }
+
+    fn wait(&self, context: &WaitContext<'_>, timeout: Option<Duration>) -> bool {
+        let event = self.event.get_or_create();
+
+        timeout.map_or_else(
+            || {
+                event.wait_while(|| context.is_pending());
+                true
+            },
+            |timeout| event.wait_while_timeout(timeout, || context.is_pending()),
+        )
+    }
+
+    fn wake_all(&self, _context: &WaitContext<'_>) {
+        // The event only exists if a thread is waiting for rundown.
+        if let Some(event) = self.event.get() {
+            event.set();
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl EventWait {
+    /// Registers `waker` to be woken once rundown completes, if rundown is
+    /// still pending, keeping the registration in `task`. Returns true if
+    /// the waker was registered.
+    #[cfg(all(target_os = "linux", not(feature = "loom")))]
+    #[allow(clippy::unused_self)]
+    pub(crate) fn register(
+        &self,
+        context: &WaitContext<'_>,
+        task: &mut Option<u64>,
+        waker: &Waker,
+    ) -> bool {
+        futex::register_while(context.ref_count, task, waker, || context.is_pending())
+    }
+
+    /// Registers `waker` to be woken once rundown completes, if rundown is
+    /// still pending, keeping the registration in `task`. Returns true if
+    /// the waker was registered.
+    #[cfg(any(not(target_os = "linux"), feature = "loom"))]
+    pub(crate) fn register(
+        &self,
+        context: &WaitContext<'_>,
+        task: &mut Option<u64>,
+        waker: &Waker,
+    ) -> bool {
+        self.event
+            .get_or_create()
+            .register_while(task, waker, || context.is_pending())
+    }
+
+    /// Removes the registration `task` made by `register`, unless the
+    /// task was already woken.
+    #[cfg(all(target_os = "linux", not(feature = "loom")))]
+    #[allow(clippy::unused_self)]
+    pub(crate) fn unregister(&self, context: &WaitContext<'_>, task: u64) {
+        futex::unregister(context.ref_count, task);
+    }
+
+    /// Removes the registration `task` made by `register`, unless the
+    /// task was already woken.
+    #[cfg(any(not(target_os = "linux"), feature = "loom"))]
+    pub(crate) fn unregister(&self, _context: &WaitContext<'_>, task: u64) {
+        // The event exists, as it was created to register the task.
+        if let Some(event) = self.event.get() {
+            event.unregister(task);
+        }
+    }
+}
+
+/// A [`WaitStrategy`] which busy waits, for latency critical threads which
+/// would rather burn CPU time than be descheduled.
+///
+/// The strategy has no state, and there is nobody to wake. Without the `std`
+/// feature there is no clock, so the strategy can't honor timeouts.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SpinWait;
+
+impl SpinWait {
+    /// Creates a [`SpinWait`].
+    #[must_use]
+    pub const fn new() -> Self {
+        Self
+    }
+}
+
+impl WaitStrategy for SpinWait {
+    fn wait(&self, context: &WaitContext<'_>, timeout: Option<Duration>) -> bool {
+        #[cfg(feature = "std")]
+        let start = Instant::now();
+        #[cfg(not(feature = "std"))]
+        let _ = timeout;
+
+        while context.is_pending() {
+            #[cfg(feature = "std")]
+            if timeout.is_some_and(|timeout| start.elapsed() >= timeout) {
+                return false;
+            }
+
+            core::hint::spin_loop();
+        }
+
+        true
+    }
+
+    #[inline]
+    fn wake_all(&self, _context: &WaitContext<'_>) {}
+}
+
+/// A [`WaitStrategy`] which yields waiting threads with `std::thread::yield_now`.
+///
+/// It suits threads which expect to wait briefly, but shouldn't keep other
+/// threads from running in the meantime. Like [`SpinWait`], the strategy has
+/// no state, and there is nobody to wake.
+#[cfg(feature = "std")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct YieldWait;
+
+#[cfg(feature = "std")]
+impl YieldWait {
+    /// Creates a [`YieldWait`].
+    #[must_use]
+    pub const fn new() -> Self {
+        Self
+    }
+}
+
+#[cfg(feature = "std")]
+impl WaitStrategy for YieldWait {
+    fn wait(&self, context: &WaitContext<'_>, timeout: Option<Duration>) -> bool {
+        let start = Instant::now();
+
+        while context.is_pending() {
+            if timeout.is_some_and(|timeout| start.elapsed() >= timeout) {
+                return false;
+            }
+
+            thread::yield_now();
+        }
+
+        true
+    }
+
+    #[inline]
+    fn wake_all(&self, _context: &WaitContext<'_>) {}
+}
+
+/// A [`WaitStrategy`] which parks waiting threads with `std::thread::park`,
+/// and has the thread which completes rundown unpark them.
+#[cfg(feature = "std")]
+#[derive(Debug, Default)]
+pub struct ParkWait {
+    /// The threads which are parked waiting for rundown.
+    parked: Mutex<Vec<Thread>>,
+}
+
+#[cfg(feature = "std")]
+impl ParkWait {
+    /// Acquires the lock on the parked threads. The list is always left
+    /// in a consistent state, so poisoning is ignored.
+    fn lock(&self) -> MutexGuard<'_, Vec<Thread>> {
+        self.parked.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(feature = "std")]
+impl WaitStrategy for ParkWait {
+    fn wait(&self, context: &WaitContext<'_>, timeout: Option<Duration>) -> bool {
+        let start = Instant::now();
+        let current = thread::current();
+
+        let completed = loop {
+            // The thread is registered before every check of the condition,
+            // so either the check observes the completion, or the thread is
+            // unparked. Waking removes the registration, and once the object
+            // is re-initialized the thread can wake to find the next rundown
+            // in progress, which it then has to be woken for as well.
+            let mut parked = self.lock();
+            if !parked.iter().any(|parked| parked.id() == current.id()) {
+                parked.push(current.clone());
+            }
+            drop(parked);
+
+            if !context.is_pending() {
+                break true;
+            }
+
+            match timeout {
+                Some(timeout) => match timeout.checked_sub(start.elapsed()) {
+                    Some(remaining) if !remaining.is_zero() => thread::park_timeout(remaining),
+                    _ => break false,
+                },
+                None => thread::park(),
+            }
+        };
+
+        // Unless the thread was woken, it is still registered.
+        self.lock().retain(|parked| parked.id() != current.id());
+
+        completed
+    }
+
+    fn wake_all(&self, _context: &WaitContext<'_>) {
+        let parked = std::mem::take(&mut *self.lock());
+
+        for thread in parked {
+            thread.unpark();
+        }
+    }
+}
+
+/// A [`WaitStrategy`] which spins for a bounded number of iterations, in case
+/// rundown completes shortly, before parking the thread like [`ParkWait`].
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct AdaptiveWait {
+    /// The number of times to check for completion before parking.
+    spins: u32,
+
+    /// The strategy used once spinning gives up.
+    park: ParkWait,
+}
+
+#[cfg(feature = "std")]
+impl AdaptiveWait {
+    /// The number of spins used by [`AdaptiveWait::default`].
+    pub const DEFAULT_SPINS: u32 = 100;
+
+    /// Creates an [`AdaptiveWait`] which checks for completion `spins`
+    /// times before parking the thread.
+    #[must_use]
+    pub fn with_spins(spins: u32) -> Self {
+        Self {
+            spins,
+            park: ParkWait::default(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for AdaptiveWait {
+    fn default() -> Self {
+        Self::with_spins(Self::DEFAULT_SPINS)
+    }
+}
+
+#[cfg(feature = "std")]
+impl WaitStrategy for AdaptiveWait {
+    fn wait(&self, context: &WaitContext<'_>, timeout: Option<Duration>) -> bool {
+        for _ in 0..self.spins {
+            if !context.is_pending() {
+                return true;
+            }
+
+            core::hint::spin_loop();
+        }
+
+        self.park.wait(context, timeout)
+    }
+
+    fn wake_all(&self, context: &WaitContext<'_>) {
+        self.park.wake_all(context);
+    }
+}
@@ -0,0 +1,161 @@
+// Copyright 2019 Brian Gianforcaro
+
+#[cfg(feature = "std")]
+use crate::{cache_aware::CacheAwareRundownRef, expedite};
+use crate::{
+    rundown_ref::RundownRef, rundown_ref::RundownWaitResult, wait::DefaultWaitStrategy,
+    wait::WaitStrategy,
+};
+use core::fmt;
+#[cfg(feature = "std")]
+use std::time::Duration;
+
+/// A handle to a rundown which is in progress, used to wait for the
+/// outstanding references to be released.
+///
+/// Rundown starts when the handle is created, and carries on regardless
+/// of the handle, dropping it neither waits for nor cancels the rundown.
+///
+/// This structure is created by the `begin_rundown` method on `RundownRef`.
+#[must_use = "dropping the waiter doesn't wait for the outstanding references"]
+pub struct RundownWaiter<'r, W: WaitStrategy = DefaultWaitStrategy> {
+    /// The run-down reference that is being run-down.
+    run_down_ref: &'r RundownRef<W>,
+}
+
+impl<'r, W: WaitStrategy> RundownWaiter<'r, W> {
+    /// Creates a new [`RundownWaiter`] for the rundown in progress on the
+    /// [`RundownRef`] provided.
+    pub(crate) const fn new(run_down_ref: &'r RundownRef<W>) -> Self {
+        Self { run_down_ref }
+    }
+
+    /// Blocks thread execution until there are no outstanding references,
+    /// using the [`WaitStrategy`] of the run-down reference.
+    ///
+    /// A thread which misses the completed rundown returns if it finds the
+    /// object re-initialized instead. If the object is already being run-down
+    /// again by then, the thread waits for that rundown to complete as well.
+    pub fn wait(&self) {
+        self.run_down_ref.wait_for_completion(None);
+    }
+
+    /// Checks whether rundown has completed, without blocking.
+    ///
+    /// Returns [`RundownWaitResult::Complete`] if rundown completed, otherwise
+    /// returns [`RundownWaitResult::TimedOut`] with the number of references
+    /// which are still outstanding.
+    pub fn try_wait(&self) -> RundownWaitResult {
+        self.run_down_ref.wait_result()
+    }
+
+    /// Like `wait`, but gives up waiting once `timeout` has elapsed.
+    ///
+    /// Returns [`RundownWaitResult::Complete`] if rundown completed, otherwise
+    /// returns [`RundownWaitResult::TimedOut`] with the number of references
+    /// that were still outstanding, which is never zero. The wait can be
+    /// resumed afterwards.
+    #[cfg(feature = "std")]
+    pub fn wait_timeout(&self, timeout: Duration) -> RundownWaitResult {
+        if self.run_down_ref.wait_for_completion(Some(timeout)) {
+            RundownWaitResult::Complete
+        } else {
+            // The last reference may have been released since the wait
+            // timed out, which is reported as complete, never as a timeout
+            // without any outstanding references.
+            self.run_down_ref.wait_result()
+        }
+    }
+
+    /// Invokes the callbacks registered with `on_expedite` by the current
+    /// holders of protection, so that blocked holders wake up, observe that
+    /// rundown has been requested and release their protection early.
+    ///
+    /// The callbacks run on the calling thread, in the order they were
+    /// registered in, and each of them is only invoked once.
+    #[cfg(feature = "std")]
+    pub fn expedite(&self) {
+        expedite::wake_holders(self.run_down_ref);
+    }
+
+    /// Returns true once there are no outstanding references, and the
+    /// rundown has completed.
+    #[inline]
+    #[must_use]
+    pub fn is_complete(&self) -> bool {
+        !self.run_down_ref.is_rundown_in_progress()
+    }
+}
+
+impl<W: WaitStrategy> fmt::Debug for RundownWaiter<'_, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RundownWaiter")
+            .field("run_down_ref", self.run_down_ref)
+            .finish()
+    }
+}
+
+/// A handle to a rundown of a [`CacheAwareRundownRef`] which is in progress,
+/// used to wait for the outstanding references to be released, like
+/// [`RundownWaiter`].
+///
+/// This structure is created by the `begin_rundown` method on `CacheAwareRundownRef`.
+#[cfg(feature = "std")]
+#[must_use = "dropping the waiter doesn't wait for the outstanding references"]
+pub struct CacheAwareRundownWaiter<'r> {
+    /// The run-down reference that is being run-down.
+    run_down_ref: &'r CacheAwareRundownRef,
+}
+
+#[cfg(feature = "std")]
+impl<'r> CacheAwareRundownWaiter<'r> {
+    /// Creates a new [`CacheAwareRundownWaiter`] for the rundown in progress
+    /// on the [`CacheAwareRundownRef`] provided.
+    pub(crate) const fn new(run_down_ref: &'r CacheAwareRundownRef) -> Self {
+        Self { run_down_ref }
+    }
+
+    /// Blocks thread execution until there are no outstanding references
+    /// on any shard.
+    pub fn wait(&self) {
+        self.run_down_ref.wait_for_completion(None);
+    }
+
+    /// Checks whether rundown has completed, without blocking, like
+    /// `RundownWaiter::try_wait`.
+    pub fn try_wait(&self) -> RundownWaitResult {
+        self.run_down_ref.wait_result()
+    }
+
+    /// Like `wait`, but gives up waiting once `timeout` has elapsed, like
+    /// `RundownWaiter::wait_timeout`.
+    pub fn wait_timeout(&self, timeout: Duration) -> RundownWaitResult {
+        if self.run_down_ref.wait_for_completion(Some(timeout)) {
+            RundownWaitResult::Complete
+        } else {
+            self.run_down_ref.wait_result()
+        }
+    }
+
+    /// Invokes the callbacks registered with `on_expedite` by the current
+    /// holders of protection, like `RundownWaiter::expedite`.
+    pub fn expedite(&self) {
+        expedite::wake_holders(self.run_down_ref);
+    }
+
+    /// Returns true once there are no outstanding references, and the
+    /// rundown has completed.
+    #[must_use]
+    pub fn is_complete(&self) -> bool {
+        self.run_down_ref.wait_result() == RundownWaitResult::Complete
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Debug for CacheAwareRundownWaiter<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CacheAwareRundownWaiter")
+            .field("run_down_ref", self.run_down_ref)
+            .finish()
+    }
+}
@@ -0,0 +1,461 @@
+// Copyright 2019 Brian Gianforcaro
+
+//! Model checking tests for the run-down protocol, which explore every
+//! interleaving of the atomic operations, locks and condition variables
+//! used by the crate. Run them with:
+//!
+//! ```text
+//! cargo test --release --features loom --test loom
+//! ```
+#![cfg(feature = "loom")]
+
+use loom::{sync::atomic::AtomicUsize, sync::atomic::Ordering, thread};
+use run_down::{
+    CacheAwareRundownRef, DropOnRundown, RundownError, RundownGuard, RundownRef, RundownRef32,
+    RundownState, TaggedRundownRef,
+};
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+};
+
+//-------------------------------------------------------------------
+// Test: loom_release_races_wait
+//
+// Description:
+//  Model the last release racing with wait_for_rundown, including
+//  the window where the release observes zero references before or
+//  after the waiter lazily creates the event. The waiter must never
+//  miss the wake-up.
+//
+#[test]
+fn loom_release_races_wait() {
+    loom::model(|| {
+        let rundown = Arc::new(RundownRef::new());
+        let guard = rundown.try_acquire_owned().unwrap();
+
+        let releaser = thread::spawn(move || drop(guard));
+
+        rundown.wait_for_rundown();
+        assert_eq!(RundownState::RundownComplete, rundown.state());
+
+        releaser.join().unwrap();
+    });
+}
+
+//-------------------------------------------------------------------
+// Test: loom_acquire_races_wait
+//
+// Description:
+//  Model an acquisition racing with wait_for_rundown. Either the
+//  acquisition fails, or the waiter waits for it to be released.
+//
+#[test]
+fn loom_acquire_races_wait() {
+    loom::model(|| {
+        let rundown = Arc::new(RundownRef::new());
+
+        let rundown_clone = Arc::clone(&rundown);
+        let acquirer = thread::spawn(move || match rundown_clone.try_acquire() {
+            Ok(guard) => drop(guard),
+            Err(error) => assert_eq!(RundownError::RundownInProgress, error),
+        });
+
+        rundown.wait_for_rundown();
+        assert_eq!(RundownState::RundownComplete, rundown.state());
+        assert!(rundown.try_acquire().is_err());
+
+        acquirer.join().unwrap();
+    });
+}
+
+//-------------------------------------------------------------------
+// Test: loom_concurrent_waiters
+//
+// Description:
+//  Model two threads waiting for rundown concurrently while the
+//  last reference is released, both must be woken.
+//
+#[test]
+fn loom_concurrent_waiters() {
+    loom::model(|| {
+        let rundown = Arc::new(RundownRef::new());
+        let guard = rundown.try_acquire_owned().unwrap();
+
+        let waiters: Vec<_> = (0..2)
+            .map(|_| {
+                let rundown_clone = Arc::clone(&rundown);
+                thread::spawn(move || rundown_clone.wait_for_rundown())
+            })
+            .collect();
+
+        drop(guard);
+
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+
+        assert_eq!(RundownState::RundownComplete, rundown.state());
+    });
+}
+
+//-------------------------------------------------------------------
+// Test: loom_wait_races_re_init
+//
+// Description:
+//  Model a waiter racing with the rundown being completed and the
+//  object being re-initialized. The waiter must not be left blocked
+//  on a rundown which already completed.
+//
+#[test]
+fn loom_wait_races_re_init() {
+    loom::model(|| {
+        let rundown = Arc::new(RundownRef::new());
+        let guard = rundown.try_acquire_owned().unwrap();
+
+        let rundown_clone = Arc::clone(&rundown);
+        let waiter = thread::spawn(move || rundown_clone.wait_for_rundown());
+
+        drop(guard);
+        rundown.wait_for_rundown();
+        assert_eq!(Ok(()), rundown.try_re_init());
+
+        waiter.join().unwrap();
+    });
+}
+
+//-------------------------------------------------------------------
+// Test: loom_split_guards_race_wait
+//
+// Description:
+//  Model the references of a guard acquired with try_acquire_many
+//  being released piecemeal while the object is run-down.
+//
+#[test]
+fn loom_split_guards_race_wait() {
+    loom::model(|| {
+        let rundown = Arc::new(RundownRef::new());
+
+        let rundown_clone = Arc::clone(&rundown);
+        let holder = thread::spawn(move || {
+            if let Ok(mut guard) = rundown_clone.try_acquire_many(2) {
+                let split = guard.split(1).unwrap();
+                drop(split);
+                drop(guard);
+            }
+        });
+
+        rundown.wait_for_rundown();
+        assert_eq!(RundownState::RundownComplete, rundown.state());
+
+        holder.join().unwrap();
+    });
+}
+
+//-------------------------------------------------------------------
+// Test: loom_async_release_races_poll
+//
+// Description:
+//  Model the last release racing with the rundown future being
+//  polled, the task must be woken once the reference is released.
+//
+#[test]
+#[cfg(feature = "async")]
+fn loom_async_release_races_poll() {
+    loom::model(|| {
+        let rundown = Arc::new(RundownRef::new());
+        let guard = rundown.try_acquire_owned().unwrap();
+
+        let releaser = thread::spawn(move || drop(guard));
+
+        loom::future::block_on(rundown.wait_for_rundown_async());
+        assert_eq!(RundownState::RundownComplete, rundown.state());
+
+        releaser.join().unwrap();
+    });
+}
+
+//-------------------------------------------------------------------
+// Test: loom_cache_aware_release_races_wait
+//
+// Description:
+//  Model the last release on a CacheAwareRundownRef racing with the
+//  waiter marking and summing the shards.
+//
+#[test]
+fn loom_cache_aware_release_races_wait() {
+    loom::model(|| {
+        let rundown = Arc::new(CacheAwareRundownRef::with_shards(2));
+
+        let rundown_clone = Arc::clone(&rundown);
+        let holder = thread::spawn(move || {
+            if let Ok(guard) = rundown_clone.try_acquire() {
+                drop(guard);
+            }
+        });
+
+        rundown.wait_for_rundown();
+        assert_eq!(RundownState::RundownComplete, rundown.state());
+
+        holder.join().unwrap();
+    });
+}
+
+//-------------------------------------------------------------------
+// Test: loom_cache_aware_wait_races_re_init
+//
+// Description:
+//  Model a thread marking the shards of a CacheAwareRundownRef while
+//  another thread completes rundown and re-initializes the object.
+//  The shards must never be left partially marked.
+//
+#[test]
+fn loom_cache_aware_wait_races_re_init() {
+    loom::model(|| {
+        let rundown = Arc::new(CacheAwareRundownRef::with_shards(2));
+
+        let rundown_clone = Arc::clone(&rundown);
+        let waiter = thread::spawn(move || rundown_clone.wait_for_rundown());
+
+        rundown.wait_for_rundown();
+        assert_eq!(Ok(()), rundown.try_re_init());
+
+        waiter.join().unwrap();
+        assert_ne!(RundownState::RundownInProgress, rundown.state());
+    });
+}
+
+//-------------------------------------------------------------------
+// Test: loom_rundown_ref32_release_races_waiters
+//
+// Description:
+//  Model the last release on a RundownRef32 racing with two threads
+//  waiting for rundown on the event it shares through the global
+//  table. Neither waiter may miss the wake-up.
+//
+#[test]
+fn loom_rundown_ref32_release_races_waiters() {
+    loom::model(|| {
+        let rundown = Arc::new(RundownRef32::new());
+
+        // The guard borrows the object, so hand the reference to the
+        // releasing thread by hand.
+        std::mem::forget(rundown.try_acquire().unwrap());
+
+        let rundown_clone = Arc::clone(&rundown);
+        let releaser = thread::spawn(move || rundown_clone.release());
+
+        let rundown_clone = Arc::clone(&rundown);
+        let waiter = thread::spawn(move || rundown_clone.wait_for_rundown());
+
+        rundown.wait_for_rundown();
+        assert_eq!(RundownState::RundownComplete, rundown.state());
+
+        releaser.join().unwrap();
+        waiter.join().unwrap();
+    });
+}
+
+//-------------------------------------------------------------------
+// Test: loom_release_underflow_races_acquire
+//
+// Description:
+//  Model a release by hand racing with an acquisition, which panics if
+//  it runs first, as no references are outstanding. The reference count
+//  must never change when it panics, so the acquisition can't observe the
+//  count under-flow into the flags.
+//
+#[test]
+fn loom_release_underflow_races_acquire() {
+    loom::model(|| {
+        let rundown = Arc::new(RundownRef::new());
+        let rundown32 = Arc::new(RundownRef32::new());
+
+        let releaser = {
+            let (rundown, rundown32) = (Arc::clone(&rundown), Arc::clone(&rundown32));
+            thread::spawn(move || {
+                (
+                    panic::catch_unwind(AssertUnwindSafe(|| rundown.release())).is_ok(),
+                    panic::catch_unwind(AssertUnwindSafe(|| rundown32.release())).is_ok(),
+                )
+            })
+        };
+
+        // Take over the references from the guards, to release them by hand.
+        std::mem::forget(rundown.try_acquire().unwrap());
+        std::mem::forget(rundown32.try_acquire().unwrap());
+
+        let (released, released32) = releaser.join().unwrap();
+        assert_eq!(u64::from(!released), rundown.outstanding_refs());
+        assert_eq!(u32::from(!released32), rundown32.outstanding_refs());
+    });
+}
+
+//-------------------------------------------------------------------
+// Test: loom_guard_by_hand_underflow_races_acquire
+//
+// Description:
+//  Model dropping a guard created by hand with RundownGuard::new, which
+//  isn't known to hold a reference, racing with an acquisition. The drop
+//  panics if it runs first, and must do so without changing the count,
+//  so the acquisition never observes rundown in progress.
+//
+#[test]
+fn loom_guard_by_hand_underflow_races_acquire() {
+    loom::model(|| {
+        let rundown = Arc::new(RundownRef::new());
+
+        let dropper = {
+            let rundown = Arc::clone(&rundown);
+            thread::spawn(move || {
+                panic::catch_unwind(AssertUnwindSafe(|| drop(RundownGuard::new(&*rundown)))).is_ok()
+            })
+        };
+
+        // Take over the reference from the guard, for the other guard to release.
+        std::mem::forget(rundown.try_acquire().unwrap());
+
+        let released = dropper.join().unwrap();
+        assert_eq!(u64::from(!released), rundown.outstanding_refs());
+        assert_eq!(RundownState::Active, rundown.state());
+    });
+}
+
+//-------------------------------------------------------------------
+// Test: loom_run_down_then_races_release
+//
+// Description:
+//  Model a completion callback being registered while the last
+//  reference is released and another thread waits for rundown. The
+//  callback must run exactly once, after the reference was released,
+//  and the waiter must be woken.
+//
+#[test]
+fn loom_run_down_then_races_release() {
+    loom::model(|| {
+        let rundown = Arc::new(RundownRef::new());
+        let guard = rundown.try_acquire_owned().unwrap();
+        let ran = Arc::new(AtomicUsize::new(0));
+
+        let releaser = thread::spawn(move || drop(guard));
+
+        let rundown_clone = Arc::clone(&rundown);
+        let waiter = thread::spawn(move || rundown_clone.wait_for_rundown());
+
+        let ran_clone = Arc::clone(&ran);
+        rundown.run_down_then(move || {
+            ran_clone.fetch_add(1, Ordering::Relaxed);
+        });
+
+        releaser.join().unwrap();
+        waiter.join().unwrap();
+
+        assert_eq!(1, ran.load(Ordering::Relaxed));
+        assert_eq!(Ok(()), rundown.try_re_init());
+    });
+}
+
+//-------------------------------------------------------------------
+// Test: loom_run_down_then_after_completion_keeps_order
+//
+// Description:
+//  Model a completion callback being registered while the thread which
+//  released the last reference is completing rundown, after an earlier
+//  callback was registered. The callbacks must run in the order they
+//  were registered in.
+//
+#[test]
+fn loom_run_down_then_after_completion_keeps_order() {
+    loom::model(|| {
+        let rundown = Arc::new(RundownRef::new());
+        let guard = rundown.try_acquire_owned().unwrap();
+        let ran = Arc::new(AtomicUsize::new(0));
+
+        let ran_clone = Arc::clone(&ran);
+        rundown.run_down_then(move || {
+            assert_eq!(0, ran_clone.fetch_add(1, Ordering::Relaxed));
+        });
+
+        let releaser = thread::spawn(move || drop(guard));
+
+        let ran_clone = Arc::clone(&ran);
+        rundown.run_down_then(move || {
+            assert_eq!(1, ran_clone.fetch_add(1, Ordering::Relaxed));
+        });
+
+        releaser.join().unwrap();
+        assert_eq!(2, ran.load(Ordering::Relaxed));
+    });
+}
+
+//-------------------------------------------------------------------
+// Test: loom_drop_on_rundown_guard_races_retire
+//
+// Description:
+//  Model a guard on a DropOnRundown being acquired and dropped while
+//  the data is retired, including the acquisition failing and backing
+//  out. The data must be dropped exactly once, after the guard was
+//  released.
+//
+#[test]
+fn loom_drop_on_rundown_guard_races_retire() {
+    struct DropCounter(Arc<AtomicUsize>);
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    loom::model(|| {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let container = Arc::new(DropOnRundown::new(DropCounter(Arc::clone(&drops))));
+
+        let container_clone = Arc::clone(&container);
+        let holder = thread::spawn(move || {
+            if let Ok(guard) = container_clone.try_acquire() {
+                assert_eq!(0, guard.0.load(Ordering::Relaxed));
+            }
+        });
+
+        container.retire();
+        holder.join().unwrap();
+
+        assert!(container.is_dropped());
+        assert_eq!(1, drops.load(Ordering::Relaxed));
+    });
+}
+
+//-------------------------------------------------------------------
+// Test: loom_tagged_claims_race
+//
+// Description:
+//  Model two threads using the same tag for the first time while a
+//  third tag is claimed. Each tag must be counted in a single slot,
+//  whose name is fully published before it is observed.
+//
+#[test]
+fn loom_tagged_claims_race() {
+    loom::model(|| {
+        let rundown_ref = Arc::new(TaggedRundownRef::new());
+
+        let rundown_clone = Arc::clone(&rundown_ref);
+        let other = thread::spawn(move || {
+            let _guard = rundown_clone.try_acquire_tagged("http").unwrap();
+            assert!(rundown_clone.outstanding_by_tag().count() <= 2);
+        });
+
+        let http = rundown_ref.try_acquire_tagged("http").unwrap();
+        let cron = rundown_ref.try_acquire_tagged("cron").unwrap();
+        other.join().unwrap();
+
+        let mut outstanding: Vec<_> = rundown_ref.outstanding_by_tag().collect();
+        outstanding.sort_unstable();
+        assert_eq!(vec![("cron", 1), ("http", 1)], outstanding);
+
+        drop(http);
+        drop(cron);
+        rundown_ref.wait_for_rundown();
+        assert_eq!(RundownState::RundownComplete, rundown_ref.state());
+    });
+}
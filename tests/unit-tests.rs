@@ -1,11 +1,19 @@
 // Copyright 2019 Brian Gianforcaro
 
+// These tests use real threads, the loom types can only be used within a model.
+#![cfg(all(feature = "std", not(feature = "loom")))]
+
 use pretty_assertions::assert_eq;
-use run_down::{RundownError, RundownGuard, RundownRef};
-use std::sync::Arc;
+use run_down::{
+    AdaptiveWait, CacheAwareRundownGuard, CacheAwareRundownRef, DropOnRundown, EventWait,
+    ExpediteRegistration, OwnedRundownGuard, ParkWait, Rundown, RundownError, RundownGuard,
+    RundownRef, RundownRef32, RundownSnapshot, RundownState, RundownTag, RundownWaitResult,
+    SpinWait, TaggedRundownRef, WaitContext, WaitStrategy, YieldWait,
+};
+use std::sync::{mpsc, Arc};
 use std::thread;
 use std::time::Duration;
-use std::{sync::atomic::AtomicBool, sync::atomic::Ordering};
+use std::{sync::atomic::AtomicBool, sync::atomic::AtomicUsize, sync::atomic::Ordering};
 
 //-------------------------------------------------------------------
 // Test: test_rundown_guard_implements_drop
@@ -123,85 +131,2252 @@ fn test_re_init_panic_on_ref() {
 }
 
 //-------------------------------------------------------------------
-// Test: test_usage_with_concurrency
+// Test: test_state_transitions
 //
 // Description:
-//  A simple test case to validate the usage of RundownRef
-//  and RundownGuard in the wild. We spawn a few threads all attempting
-//  to acquire rundown protection, and holding it for a small duration.
-//  In parallel we attempt to run-down the ref so that no one can acquire
-//  rundown protection, and we wait for that to complete. On my machine
-//  most of the threads succeed, while one of the last threads fail as
-//  the object was successfully run-down.
+//  Test that the state reported by RundownRef follows the rundown
+//  from active, through rundown in progress, to rundown complete.
 //
 #[test]
-fn test_usage_with_concurrency() {
-    let mut children = vec![];
+fn test_state_transitions() {
+    let rundown_ref = RundownRef::new();
+    assert_eq!(RundownState::Active, rundown_ref.state());
+
+    let guard = rundown_ref.try_acquire().unwrap();
+    let _ = rundown_ref.wait_for_rundown_timeout(Duration::from_millis(1));
+    assert_eq!(RundownState::RundownInProgress, rundown_ref.state());
+
+    std::mem::drop(guard);
+    assert_eq!(RundownState::RundownComplete, rundown_ref.state());
+
+    rundown_ref.re_init();
+    assert_eq!(RundownState::Active, rundown_ref.state());
+}
+
+//-------------------------------------------------------------------
+// Test: test_try_re_init
+//
+// Description:
+//  Test that try_re_init reports an error for each state in which
+//  re-initialization is not allowed, and succeeds once complete.
+//
+#[test]
+fn test_try_re_init() {
+    let rundown_ref = RundownRef::new();
+    assert_eq!(rundown_ref.try_re_init(), Err(RundownError::NotRunDown));
+
+    let guard = rundown_ref.try_acquire().unwrap();
+    let _ = rundown_ref.wait_for_rundown_timeout(Duration::from_millis(1));
+    assert_eq!(
+        rundown_ref.try_re_init(),
+        Err(RundownError::ReferencesOutstanding)
+    );
+
+    std::mem::drop(guard);
+    assert_eq!(rundown_ref.try_re_init(), Ok(()));
+
+    // A second re-init fails, as the object is active again.
+    assert_eq!(rundown_ref.try_re_init(), Err(RundownError::NotRunDown));
+}
+
+//-------------------------------------------------------------------
+// Test: test_acquire_many
+//
+// Description:
+//  Test that a guard from try_acquire_many holds all of the acquired
+//  references, and releases all of them when dropped.
+//
+#[test]
+fn test_acquire_many() {
+    let rundown_ref = RundownRef::new();
+
+    let guard = rundown_ref.try_acquire_many(5).unwrap();
+    assert_eq!(5, guard.count());
+
+    let result = rundown_ref.wait_for_rundown_timeout(Duration::from_millis(1));
+    assert_eq!(result, RundownWaitResult::TimedOut { outstanding: 5 });
+
+    let result = rundown_ref.try_acquire_many(5);
+    assert_eq!(result.err(), Some(RundownError::RundownInProgress));
+
+    std::mem::drop(guard);
+    assert_eq!(RundownState::RundownComplete, rundown_ref.state());
+}
+
+//-------------------------------------------------------------------
+// Test: test_acquire_many_split
+//
+// Description:
+//  Test that splitting a guard moves references to the new guard,
+//  that each guard releases only its own share, and that invalid
+//  splits are rejected.
+//
+#[test]
+fn test_acquire_many_split() {
     let rundown = Arc::new(RundownRef::new());
+    let mut guard = rundown.try_acquire_many(4).unwrap();
 
-    for _ in 0..20 {
+    assert!(guard.split(0).is_none());
+    assert!(guard.split(4).is_none());
+
+    let first = guard.split(1).unwrap();
+    let second = guard.split(2).unwrap();
+    assert_eq!(1, first.count());
+    assert_eq!(2, second.count());
+    assert_eq!(1, guard.count());
+
+    let _ = rundown.wait_for_rundown_timeout(Duration::from_millis(1));
+
+    std::mem::drop(second);
+    let result = rundown.wait_for_rundown_timeout(Duration::from_millis(1));
+    assert_eq!(result, RundownWaitResult::TimedOut { outstanding: 2 });
+
+    thread::scope(|scope| {
+        scope.spawn(move || std::mem::drop(first));
+        scope.spawn(move || std::mem::drop(guard));
+    });
+
+    assert_eq!(RundownState::RundownComplete, rundown.state());
+}
+
+//-------------------------------------------------------------------
+// Test: test_owned_guard_is_send_and_static
+//
+// Description:
+//  Test that OwnedRundownGuard can be moved to other threads.
+//
+#[test]
+fn test_owned_guard_is_send_and_static() {
+    // Test via compilation.
+    fn is_send_static<T: Send + 'static>() {}
+    is_send_static::<OwnedRundownGuard>();
+
+    // Verify with needs_drop as well.
+    assert!(std::mem::needs_drop::<OwnedRundownGuard>());
+}
+
+//-------------------------------------------------------------------
+// Test: test_owned_guard_outlives_scope
+//
+// Description:
+//  Test that protection acquired via try_acquire_owned is held by
+//  the thread the guard was moved to, until the guard is dropped.
+//
+#[test]
+fn test_owned_guard_outlives_scope() {
+    let rundown = Arc::new(RundownRef::new());
+
+    let guard = rundown.try_acquire_owned().unwrap();
+    let holder = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        std::mem::drop(guard);
+    });
+
+    let result = rundown.wait_for_rundown_timeout(Duration::from_millis(1));
+    assert_eq!(result, RundownWaitResult::TimedOut { outstanding: 1 });
+
+    rundown.wait_for_rundown();
+    holder.join().unwrap();
+
+    let result = rundown.try_acquire_owned();
+    assert_eq!(result.err(), Some(RundownError::RundownInProgress));
+}
+
+//-------------------------------------------------------------------
+// Test: test_rundown_access
+//
+// Description:
+//  Test that Rundown<T> gives shared access to the data until it
+//  is run-down, and exclusive access once it has been.
+//
+#[test]
+fn test_rundown_access() {
+    let rundown = Rundown::new(vec![1, 2, 3]);
+
+    {
+        let first = rundown.try_access().unwrap();
+        let second = rundown.try_access().unwrap();
+        assert_eq!(*first, *second);
+    }
+
+    let length = rundown.run_down_with(|data| {
+        data.push(4);
+        data.len()
+    });
+    assert_eq!(4, length);
+
+    let result = rundown.try_access();
+    assert_eq!(result.err(), Some(RundownError::RundownInProgress));
+
+    rundown.re_init();
+    assert_eq!(vec![1, 2, 3, 4], *rundown.try_access().unwrap());
+}
+
+//-------------------------------------------------------------------
+// Test: test_rundown_and_take_waits_for_access
+//
+// Description:
+//  Test that run_down_and_take waits for outstanding shared access
+//  on other threads to drain before taking the data.
+//
+#[test]
+fn test_rundown_and_take_waits_for_access() {
+    let rundown = Arc::new(Rundown::new(String::from("data")));
+    let accessed = Arc::new(std::sync::Barrier::new(2));
+    let released = Arc::new(AtomicBool::new(false));
+
+    let rundown_clone = Arc::clone(&rundown);
+    let accessed_clone = Arc::clone(&accessed);
+    let released_clone = Arc::clone(&released);
+    let reader = thread::spawn(move || {
+        let data = rundown_clone.try_access().unwrap();
+        accessed_clone.wait();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!("data", data.as_str());
+        released_clone.store(true, Ordering::SeqCst);
+    });
+
+    accessed.wait();
+    let taken = rundown.run_down_and_take();
+    assert!(released.load(Ordering::SeqCst));
+    assert_eq!("data", taken);
+
+    reader.join().unwrap();
+    rundown.re_init();
+    assert_eq!("", rundown.try_access().unwrap().as_str());
+}
+
+//-------------------------------------------------------------------
+// Test: test_rundown_re_init_panic_without_rundown
+//
+// Description:
+//  Test that re_init on Rundown<T> without running-down panics.
+//
+#[test]
+#[should_panic]
+fn test_rundown_re_init_panic_without_rundown() {
+    let rundown = Rundown::new(0);
+
+    // Re-init should panic as run-down has not occurred.
+    rundown.re_init();
+}
+
+//-------------------------------------------------------------------
+// Test: test_cache_aware_acquisition_and_rundown
+//
+// Description:
+//  Test that CacheAwareRundownRef hands out protection until it is
+//  run-down, rejects it afterwards, and can be re-initialized.
+//
+#[test]
+fn test_cache_aware_acquisition_and_rundown() {
+    let rundown_ref = CacheAwareRundownRef::with_shards(4);
+
+    {
+        let _guard: CacheAwareRundownGuard = rundown_ref.try_acquire().unwrap();
+        let result = rundown_ref.wait_for_rundown_timeout(Duration::from_millis(10));
+        assert_eq!(result, RundownWaitResult::TimedOut { outstanding: 1 });
+    }
+
+    rundown_ref.wait_for_rundown();
+
+    let result = rundown_ref.try_acquire();
+    assert_eq!(result.err(), Some(RundownError::RundownInProgress));
+
+    rundown_ref.re_init();
+    assert!(rundown_ref.try_acquire().is_ok());
+}
+
+//-------------------------------------------------------------------
+// Test: test_cache_aware_re_init_panic_without_rundown
+//
+// Description:
+//  Test that re_init without running-down the object panics.
+//
+#[test]
+#[should_panic]
+fn test_cache_aware_re_init_panic_without_rundown() {
+    let rundown_ref = CacheAwareRundownRef::new();
+
+    // Re-init should panic as run-down has not occurred.
+    rundown_ref.re_init();
+}
+
+//-------------------------------------------------------------------
+// Test: test_cache_aware_usage_with_concurrency
+//
+// Description:
+//  Test that wait_for_rundown waits for protection held on every
+//  shard, by holding protection from more threads than shards.
+//
+#[test]
+fn test_cache_aware_usage_with_concurrency() {
+    let mut children = vec![];
+    let rundown = Arc::new(CacheAwareRundownRef::with_shards(2));
+    let acquired = Arc::new(std::sync::Barrier::new(9));
+    let released = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+    for _ in 0..8 {
         let rundown_clone = Arc::clone(&rundown);
+        let acquired_clone = Arc::clone(&acquired);
+        let released_clone = Arc::clone(&released);
 
         children.push(thread::spawn(move || {
-            if let Ok(_guard) = rundown_clone.try_acquire() {
-                thread::sleep(Duration::from_millis(10));
-            }
+            let guard = rundown_clone.try_acquire().unwrap();
+            acquired_clone.wait();
+            thread::sleep(Duration::from_millis(10));
+            released_clone.fetch_add(1, Ordering::SeqCst);
+            std::mem::drop(guard);
         }));
     }
 
+    acquired.wait();
     rundown.wait_for_rundown();
+    assert_eq!(8, released.load(Ordering::SeqCst));
 
     for child in children {
-        let _ = child.join();
+        child.join().unwrap();
     }
 }
 
 //-------------------------------------------------------------------
-// Test: test_mini_stress
+// Test: test_cache_aware_release_races_marking
 //
 // Description:
-//  A simple mini stress test to validate the usage of RundownRef
-//  and RundownGuard in a situation with contention.
+//  Test that the waiter is woken when the last references are released
+//  on shards which drain while the waiter is still marking the other
+//  shards. Each release only observes its own shard, so it must signal
+//  the waiter whenever its shard drains during rundown.
 //
 #[test]
-fn test_mini_stress() {
+fn test_cache_aware_release_races_marking() {
+    for _ in 0..200 {
+        let rundown = CacheAwareRundownRef::with_shards(64);
+        let barrier = std::sync::Barrier::new(5);
+
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    let guard = rundown.try_acquire().unwrap();
+                    barrier.wait();
+                    drop(guard);
+                });
+            }
+
+            barrier.wait();
+            assert_eq!(
+                RundownWaitResult::Complete,
+                rundown.wait_for_rundown_timeout(Duration::from_secs(10))
+            );
+        });
+
+        assert_eq!(RundownState::RundownComplete, rundown.state());
+    }
+}
+
+//-------------------------------------------------------------------
+// Test: test_cache_aware_release_by_hand_on_holder_shard
+//
+// Description:
+//  Test that a reference released by hand, by a thread on the shard of
+//  another guard, leaves the guards able to release their references.
+//  The manual release takes the reference of the guard from its shard,
+//  so the guard has to release the one left on the other shard instead.
+//
+#[test]
+fn test_cache_aware_release_by_hand_on_holder_shard() {
+    // Returns the index of the shard the guard was acquired on.
+    fn shard_of(guard: &CacheAwareRundownGuard<'_>) -> String {
+        let debug = format!("{:?}", guard);
+        let start = debug.find("shard: ").unwrap() + "shard: ".len();
+        let end = start + debug[start..].find(',').unwrap();
+        debug[start..end].to_owned()
+    }
+
+    let rundown = Arc::new(CacheAwareRundownRef::with_shards(2));
+    let guard = rundown.try_acquire_many(2).unwrap();
+    let owned = rundown.try_acquire_owned().unwrap();
+    let holder_shard = shard_of(&guard);
+
+    // Pin a reference to the other shard, by leaking the guard of the
+    // first thread which is assigned to it.
+    let leaked = (0..64).any(|_| {
+        thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let leaked = rundown.try_acquire().unwrap();
+                    let other_shard = shard_of(&leaked) != holder_shard;
+                    if other_shard {
+                        std::mem::forget(leaked);
+                    }
+                    other_shard
+                })
+                .join()
+                .unwrap()
+        })
+    });
+    assert!(leaked);
+    assert_eq!(4, rundown.outstanding_refs());
+
+    // Release the leaked reference from the shard of the guards.
+    rundown.release();
+    assert_eq!(3, rundown.outstanding_refs());
+
+    drop(owned);
+    drop(guard);
+    assert_eq!(0, rundown.outstanding_refs());
+    assert_eq!(
+        RundownWaitResult::Complete,
+        rundown.wait_for_rundown_timeout(Duration::from_secs(10))
+    );
+}
+
+//-------------------------------------------------------------------
+// Test helper: rundown_api_test
+//
+// Description:
+//  Defines a test which runs the same code against a run-down
+//  reference, so RundownRef and CacheAwareRundownRef are checked
+//  to behave the same through the methods they share.
+//
+macro_rules! rundown_api_test {
+    ($name:ident, $new:expr) => {
+        #[test]
+        fn $name() {
+            let rundown_ref = Arc::new($new);
+            assert_eq!(
+                RundownSnapshot {
+                    state: RundownState::Active,
+                    outstanding_refs: 0,
+                },
+                rundown_ref.snapshot()
+            );
+
+            let guard = rundown_ref.try_acquire().unwrap();
+            let many = rundown_ref.try_acquire_many(3).unwrap();
+            let owned = rundown_ref.try_acquire_owned().unwrap();
+            assert_eq!(3, many.count());
+            assert_eq!(5, rundown_ref.outstanding_refs());
+            assert!(!guard.is_rundown_requested());
+
+            // Protection can be released by hand, by another thread.
+            std::mem::forget(guard);
+            let rundown_clone = Arc::clone(&rundown_ref);
+            thread::spawn(move || rundown_clone.release())
+                .join()
+                .unwrap();
+            assert_eq!(4, rundown_ref.outstanding_refs());
+
+            let waiter = rundown_ref.begin_rundown();
+            assert!(rundown_ref.is_rundown_in_progress());
+            assert!(many.is_rundown_requested());
+            assert!(owned.is_rundown_requested());
+            assert!(format!("{:?}", rundown_ref).contains("RundownInProgress"));
+            assert_eq!(
+                Some(RundownError::RundownInProgress),
+                rundown_ref.try_acquire().err()
+            );
+            assert_eq!(
+                RundownWaitResult::TimedOut { outstanding: 4 },
+                waiter.try_wait()
+            );
+            assert_eq!(
+                RundownWaitResult::TimedOut { outstanding: 4 },
+                waiter.wait_timeout(Duration::from_millis(10))
+            );
+
+            thread::spawn(move || drop(owned)).join().unwrap();
+            drop(many);
+            waiter.wait();
+            assert!(waiter.is_complete());
+            assert!(rundown_ref.is_rundown_complete());
+            assert_eq!(
+                Err(RundownError::ReferenceUnderflow),
+                rundown_ref.try_release()
+            );
+
+            rundown_ref.re_init();
+
+            #[cfg(feature = "async")]
+            {
+                let guard = rundown_ref.try_acquire_owned().unwrap();
+                let releaser = thread::spawn(move || {
+                    thread::sleep(Duration::from_millis(10));
+                    drop(guard);
+                });
+
+                block_on(rundown_ref.wait_for_rundown_async());
+                releaser.join().unwrap();
+                assert!(rundown_ref.is_rundown_complete());
+            }
+        }
+    };
+}
+
+rundown_api_test!(test_rundown_ref_api, RundownRef::new());
+rundown_api_test!(test_cache_aware_api, CacheAwareRundownRef::with_shards(4));
+
+//-------------------------------------------------------------------
+// Test: test_multiple_concurrent_waiters
+//
+// Description:
+//  Test that several threads can wait for rundown at the same time,
+//  and that all of them are woken once the protection is released.
+//
+#[test]
+fn test_multiple_concurrent_waiters() {
+    let rundown = Arc::new(RundownRef::new());
+    let released = Arc::new(AtomicBool::new(false));
+    let guard = rundown.try_acquire_owned().unwrap();
+
+    let waiters: Vec<_> = (0..8)
+        .map(|_| {
+            let rundown_clone = Arc::clone(&rundown);
+            let released_clone = Arc::clone(&released);
+            thread::spawn(move || {
+                rundown_clone.wait_for_rundown();
+                assert!(released_clone.load(Ordering::SeqCst));
+            })
+        })
+        .collect();
+
+    thread::sleep(Duration::from_millis(50));
+    released.store(true, Ordering::SeqCst);
+    std::mem::drop(guard);
+
+    for waiter in waiters {
+        waiter.join().unwrap();
+    }
+
+    assert_eq!(RundownState::RundownComplete, rundown.state());
+}
+
+//-------------------------------------------------------------------
+// Test: test_multiple_waiters_racing_releasers
+//
+// Description:
+//  Test that several waiters racing with many threads releasing
+//  their protection are all woken once the last one is released.
+//
+#[test]
+fn test_multiple_waiters_racing_releasers() {
+    for _ in 0..50 {
+        let rundown = Arc::new(RundownRef::new());
+        let mut children = vec![];
+
+        for _ in 0..4 {
+            let guard = rundown.try_acquire_owned().unwrap();
+            children.push(thread::spawn(move || {
+                thread::yield_now();
+                std::mem::drop(guard);
+            }));
+        }
+
+        for _ in 0..4 {
+            let rundown_clone = Arc::clone(&rundown);
+            children.push(thread::spawn(move || rundown_clone.wait_for_rundown()));
+        }
+
+        for child in children {
+            child.join().unwrap();
+        }
+
+        assert_eq!(RundownState::RundownComplete, rundown.state());
+    }
+}
+
+//-------------------------------------------------------------------
+// Test: test_multiple_waiters_racing_re_init
+//
+// Description:
+//  A stress test with several threads waiting for rundown, while
+//  other threads acquire protection and one thread repeatedly
+//  re-initializes the object. No waiter may be left blocked on a
+//  rundown which was already completed and re-initialized.
+//
+#[test]
+fn test_multiple_waiters_racing_re_init() {
     let mut children = vec![];
     let stop_flag = Arc::new(AtomicBool::new(false));
     let rundown = Arc::new(RundownRef::new());
 
-    for _ in 0..25 {
+    for _ in 0..4 {
         let rundown_clone = Arc::clone(&rundown);
         let stop_flag_clone = Arc::clone(&stop_flag);
-        children.push(thread::spawn(move || loop {
-            if stop_flag_clone.load(Ordering::SeqCst) {
-                break;
+        children.push(thread::spawn(move || {
+            while !stop_flag_clone.load(Ordering::SeqCst) {
+                if let Ok(_guard) = rundown_clone.try_acquire() {
+                    thread::yield_now();
+                }
             }
+        }));
+    }
 
-            if let Ok(_guard) = rundown_clone.try_acquire() {
-                thread::yield_now();
+    for _ in 0..4 {
+        let rundown_clone = Arc::clone(&rundown);
+        let stop_flag_clone = Arc::clone(&stop_flag);
+        children.push(thread::spawn(move || {
+            while !stop_flag_clone.load(Ordering::SeqCst) {
+                rundown_clone.wait_for_rundown();
             }
         }));
     }
 
-    // Launch a thread that repeadedly runs-down the object
-    // and then re-initializes it.
-    //
-    let rundown_clone_2 = Arc::clone(&rundown);
-    let stop_flag_clone_2 = Arc::clone(&stop_flag);
-    children.push(thread::spawn(move || loop {
-        if stop_flag_clone_2.load(Ordering::SeqCst) {
-            break;
+    let rundown_clone = Arc::clone(&rundown);
+    let stop_flag_clone = Arc::clone(&stop_flag);
+    children.push(thread::spawn(move || {
+        while !stop_flag_clone.load(Ordering::SeqCst) {
+            rundown_clone.wait_for_rundown();
+            let _ = rundown_clone.try_re_init();
         }
-
-        rundown_clone_2.wait_for_rundown();
-        rundown_clone_2.re_init();
     }));
 
-    thread::sleep(Duration::from_secs(10));
+    thread::sleep(Duration::from_secs(2));
 
     stop_flag.store(true, Ordering::SeqCst);
 
     for child in children {
-        let _ = child.join();
+        child.join().unwrap();
     }
 }
+
+//-------------------------------------------------------------------
+// Test: test_usage_with_concurrency
+//
+// Description:
+//  A simple test case to validate the usage of RundownRef
+//  and RundownGuard in the wild. We spawn a few threads all attempting
+//  to acquire rundown protection, and holding it for a small duration.
+//  In parallel we attempt to run-down the ref so that no one can acquire
+//  rundown protection, and we wait for that to complete. On my machine
+//  most of the threads succeed, while one of the last threads fail as
+//  the object was successfully run-down.
+//
+#[test]
+fn test_usage_with_concurrency() {
+    let mut children = vec![];
+    let rundown = Arc::new(RundownRef::new());
+
+    for _ in 0..20 {
+        let rundown_clone = Arc::clone(&rundown);
+
+        children.push(thread::spawn(move || {
+            if let Ok(_guard) = rundown_clone.try_acquire() {
+                thread::sleep(Duration::from_millis(10));
+            }
+        }));
+    }
+
+    rundown.wait_for_rundown();
+
+    for child in children {
+        let _ = child.join();
+    }
+}
+
+//-------------------------------------------------------------------
+// Test: test_mini_stress
+//
+// Description:
+//  A simple mini stress test to validate the usage of RundownRef
+//  and RundownGuard in a situation with contention.
+//
+#[test]
+fn test_mini_stress() {
+    let mut children = vec![];
+    let stop_flag = Arc::new(AtomicBool::new(false));
+    let rundown = Arc::new(RundownRef::new());
+
+    for _ in 0..25 {
+        let rundown_clone = Arc::clone(&rundown);
+        let stop_flag_clone = Arc::clone(&stop_flag);
+        children.push(thread::spawn(move || loop {
+            if stop_flag_clone.load(Ordering::SeqCst) {
+                break;
+            }
+
+            if let Ok(_guard) = rundown_clone.try_acquire() {
+                thread::yield_now();
+            }
+        }));
+    }
+
+    // Launch a thread that repeadedly runs-down the object
+    // and then re-initializes it.
+    //
+    let rundown_clone_2 = Arc::clone(&rundown);
+    let stop_flag_clone_2 = Arc::clone(&stop_flag);
+    children.push(thread::spawn(move || loop {
+        if stop_flag_clone_2.load(Ordering::SeqCst) {
+            break;
+        }
+
+        rundown_clone_2.wait_for_rundown();
+        rundown_clone_2.re_init();
+    }));
+
+    thread::sleep(Duration::from_secs(10));
+
+    stop_flag.store(true, Ordering::SeqCst);
+
+    for child in children {
+        let _ = child.join();
+    }
+}
+
+//-------------------------------------------------------------------
+// Test: test_wait_timeout_without_references
+//
+// Description:
+//  Test that a timed wait completes when there is no outstanding
+//  protection on the object.
+//
+#[test]
+fn test_wait_timeout_without_references() {
+    let rundown_ref = RundownRef::new();
+
+    let result = rundown_ref.wait_for_rundown_timeout(Duration::from_millis(10));
+    assert_eq!(result, RundownWaitResult::Complete);
+}
+
+//-------------------------------------------------------------------
+// Test: test_wait_timeout_when_protected
+//
+// Description:
+//  Test that a timed wait reports the outstanding references when
+//  it times out, that the object stays run-down, and that a later
+//  wait completes once the protection is released.
+//
+#[test]
+fn test_wait_timeout_when_protected() {
+    let rundown_ref = RundownRef::new();
+    let guard_1 = rundown_ref.try_acquire().unwrap();
+    let guard_2 = rundown_ref.try_acquire().unwrap();
+
+    let result = rundown_ref.wait_for_rundown_timeout(Duration::from_millis(10));
+    assert_eq!(result, RundownWaitResult::TimedOut { outstanding: 2 });
+
+    // Rundown is still in progress after the timeout.
+    let result = rundown_ref.try_acquire();
+    assert_eq!(result.err(), Some(RundownError::RundownInProgress));
+
+    std::mem::drop(guard_1);
+    let result = rundown_ref.wait_for_rundown_timeout(Duration::from_millis(10));
+    assert_eq!(result, RundownWaitResult::TimedOut { outstanding: 1 });
+
+    std::mem::drop(guard_2);
+    let result = rundown_ref.wait_for_rundown_timeout(Duration::from_millis(10));
+    assert_eq!(result, RundownWaitResult::Complete);
+
+    rundown_ref.re_init();
+}
+
+//-------------------------------------------------------------------
+// Test helper: wait_with_strategy
+//
+// Description:
+//  Waits for rundown from several threads on a RundownRef which uses
+//  the wait strategy provided, and verifies that all of them return
+//  once the outstanding protection is released.
+//
+fn wait_with_strategy<W: WaitStrategy + Send + Sync + 'static>(strategy: W) {
+    let rundown = Arc::new(RundownRef::with_wait_strategy(strategy));
+    let released = Arc::new(AtomicBool::new(false));
+    let guard = rundown.try_acquire_owned().unwrap();
+
+    let waiters: Vec<_> = (0..4)
+        .map(|_| {
+            let rundown_clone = Arc::clone(&rundown);
+            let released_clone = Arc::clone(&released);
+            thread::spawn(move || {
+                rundown_clone.wait_for_rundown();
+                assert!(released_clone.load(Ordering::SeqCst));
+            })
+        })
+        .collect();
+
+    thread::sleep(Duration::from_millis(20));
+    released.store(true, Ordering::SeqCst);
+    std::mem::drop(guard);
+
+    for waiter in waiters {
+        waiter.join().unwrap();
+    }
+
+    assert_eq!(RundownState::RundownComplete, rundown.state());
+    rundown.re_init();
+    assert!(rundown.try_acquire().is_ok());
+}
+
+//-------------------------------------------------------------------
+// Test helper: wait_timeout_with_strategy
+//
+// Description:
+//  Verifies that a timed wait on a RundownRef which uses the wait
+//  strategy provided times out while protection is outstanding, and
+//  completes once it is released.
+//
+fn wait_timeout_with_strategy<W: WaitStrategy>(strategy: W) {
+    let rundown_ref = RundownRef::with_wait_strategy(strategy);
+    let guard = rundown_ref.try_acquire().unwrap();
+
+    let result = rundown_ref.wait_for_rundown_timeout(Duration::from_millis(10));
+    assert_eq!(result, RundownWaitResult::TimedOut { outstanding: 1 });
+
+    std::mem::drop(guard);
+    let result = rundown_ref.wait_for_rundown_timeout(Duration::from_millis(10));
+    assert_eq!(result, RundownWaitResult::Complete);
+}
+
+//-------------------------------------------------------------------
+// Test helper: wait_races_re_init_with_strategy
+//
+// Description:
+//  Verifies that a thread waiting on a RundownRef which uses the wait
+//  strategy provided is woken by the next rundown, if the rundown it
+//  waited for completes and the object is re-initialized and run-down
+//  again before the thread observes the completion.
+//
+fn wait_races_re_init_with_strategy<W: WaitStrategy + Send + Sync + 'static>(strategy: W) {
+    let rundown = Arc::new(RundownRef::with_wait_strategy(strategy));
+
+    for _ in 0..50 {
+        let guard = rundown.try_acquire_owned().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let rundown_clone = Arc::clone(&rundown);
+        let waiter = thread::spawn(move || {
+            rundown_clone.wait_for_rundown();
+            sender.send(()).unwrap();
+        });
+
+        // Complete the rundown once the thread waits, and start the next
+        // one before the woken thread gets to check the object again.
+        thread::sleep(Duration::from_millis(1));
+        std::mem::drop(guard);
+        rundown.re_init();
+        let guard = rundown.try_acquire().unwrap();
+        let _waiter = rundown.begin_rundown();
+        thread::sleep(Duration::from_millis(1));
+        std::mem::drop(guard);
+
+        assert!(receiver.recv_timeout(Duration::from_secs(10)).is_ok());
+        waiter.join().unwrap();
+        rundown.re_init();
+    }
+}
+
+//-------------------------------------------------------------------
+// Test: test_event_wait_strategy
+//
+// Description:
+//  Test that an explicitly specified default strategy behaves like
+//  the RundownRef created with new.
+//
+#[test]
+fn test_event_wait_strategy() {
+    wait_with_strategy(EventWait::default());
+    wait_timeout_with_strategy(EventWait::default());
+}
+
+//-------------------------------------------------------------------
+// Test: test_spin_wait_strategy
+//
+// Description:
+//  Test waiting for rundown with threads which spin.
+//
+#[test]
+fn test_spin_wait_strategy() {
+    wait_with_strategy(SpinWait);
+    wait_timeout_with_strategy(SpinWait);
+}
+
+//-------------------------------------------------------------------
+// Test: test_yield_wait_strategy
+//
+// Description:
+//  Test waiting for rundown with threads which yield.
+//
+#[test]
+fn test_yield_wait_strategy() {
+    wait_with_strategy(YieldWait);
+    wait_timeout_with_strategy(YieldWait::new());
+}
+
+//-------------------------------------------------------------------
+// Test: test_park_wait_strategy
+//
+// Description:
+//  Test waiting for rundown with threads which park.
+//
+#[test]
+fn test_park_wait_strategy() {
+    wait_with_strategy(ParkWait::default());
+    wait_timeout_with_strategy(ParkWait::default());
+    wait_races_re_init_with_strategy(ParkWait::default());
+}
+
+//-------------------------------------------------------------------
+// Test: test_adaptive_wait_strategy
+//
+// Description:
+//  Test waiting for rundown with threads which spin, then park.
+//
+#[test]
+fn test_adaptive_wait_strategy() {
+    wait_with_strategy(AdaptiveWait::default());
+    wait_with_strategy(AdaptiveWait::with_spins(0));
+    wait_timeout_with_strategy(AdaptiveWait::with_spins(10));
+    wait_races_re_init_with_strategy(AdaptiveWait::with_spins(0));
+}
+
+//-------------------------------------------------------------------
+// Test: test_custom_wait_strategy
+//
+// Description:
+//  Test that a strategy defined outside of the crate, a deterministic
+//  yield loop, is used to wait and is woken by the last release.
+//
+#[test]
+fn test_custom_wait_strategy() {
+    #[derive(Default, Clone)]
+    struct YieldWait {
+        waits: Arc<AtomicUsize>,
+        wakes: Arc<AtomicUsize>,
+    }
+
+    impl WaitStrategy for YieldWait {
+        fn wait(&self, context: &WaitContext<'_>, _timeout: Option<Duration>) -> bool {
+            self.waits.fetch_add(1, Ordering::SeqCst);
+            while context.is_pending() {
+                thread::yield_now();
+            }
+            true
+        }
+
+        fn wake_all(&self, _context: &WaitContext<'_>) {
+            self.wakes.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let strategy = YieldWait::default();
+    let rundown = Arc::new(RundownRef::with_wait_strategy(strategy.clone()));
+    let guard: OwnedRundownGuard<YieldWait> = rundown.try_acquire_owned().unwrap();
+
+    let rundown_clone = Arc::clone(&rundown);
+    let waiter = thread::spawn(move || rundown_clone.wait_for_rundown());
+
+    while rundown.state() == RundownState::Active {
+        thread::yield_now();
+    }
+
+    std::mem::drop(guard);
+    waiter.join().unwrap();
+
+    assert_eq!(RundownState::RundownComplete, rundown.state());
+    assert_eq!(1, strategy.waits.load(Ordering::SeqCst));
+    assert_eq!(1, strategy.wakes.load(Ordering::SeqCst));
+
+    // Waiting once rundown is complete doesn't involve the strategy.
+    rundown.wait_for_rundown();
+    assert_eq!(1, strategy.waits.load(Ordering::SeqCst));
+}
+
+//-------------------------------------------------------------------
+// Test helper: block_on
+//
+// Description:
+//  A minimal executor which polls a future to completion on the
+//  current thread, parking the thread while the future is pending.
+//
+#[cfg(feature = "async")]
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    use std::task::{Context, Poll, Wake, Waker};
+
+    struct ThreadWaker(thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let mut future = Box::pin(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut context = Context::from_waker(&waker);
+
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+//-------------------------------------------------------------------
+// Test: test_async_wait_without_references
+//
+// Description:
+//  Test that the rundown future completes immediately when there
+//  is no outstanding protection, and that the object is run-down.
+//
+#[test]
+#[cfg(feature = "async")]
+fn test_async_wait_without_references() {
+    let rundown_ref = RundownRef::new();
+
+    block_on(rundown_ref.wait_for_rundown_async());
+
+    let result = rundown_ref.try_acquire();
+    assert_eq!(result.err(), Some(RundownError::RundownInProgress));
+}
+
+//-------------------------------------------------------------------
+// Test: test_async_wait_is_lazy
+//
+// Description:
+//  Test that creating the rundown future does not start rundown,
+//  the object is only marked once the future is polled.
+//
+#[test]
+#[cfg(feature = "async")]
+fn test_async_wait_is_lazy() {
+    let rundown_ref = RundownRef::new();
+
+    let future = rundown_ref.wait_for_rundown_async();
+    assert!(rundown_ref.try_acquire().is_ok());
+
+    block_on(future);
+    assert!(rundown_ref.try_acquire().is_err());
+}
+
+//-------------------------------------------------------------------
+// Test: test_async_wait_when_protected
+//
+// Description:
+//  Test that the rundown future is woken by the thread which
+//  releases the last outstanding protection.
+//
+#[test]
+#[cfg(feature = "async")]
+fn test_async_wait_when_protected() {
+    let rundown = Arc::new(RundownRef::new());
+    let acquired = Arc::new(std::sync::Barrier::new(2));
+
+    let rundown_clone = Arc::clone(&rundown);
+    let acquired_clone = Arc::clone(&acquired);
+    let holder = thread::spawn(move || {
+        let _guard = rundown_clone.try_acquire().unwrap();
+        acquired_clone.wait();
+        thread::sleep(Duration::from_millis(50));
+    });
+
+    acquired.wait();
+    block_on(rundown.wait_for_rundown_async());
+
+    holder.join().unwrap();
+    rundown.re_init();
+    assert!(rundown.try_acquire().is_ok());
+}
+
+//-------------------------------------------------------------------
+// Test: test_async_wait_dropped_while_pending
+//
+// Description:
+//  Test that a rundown future which is dropped while rundown is
+//  pending releases the waker of its task, which is then never woken,
+//  and that the waker of a leaked future is released along with the
+//  run-down reference.
+//
+#[test]
+#[cfg(feature = "async")]
+fn test_async_wait_dropped_while_pending() {
+    use std::future::Future;
+    use std::task::{Context, Wake, Waker};
+
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+    let waker = Waker::from(Arc::clone(&counter));
+    let mut context = Context::from_waker(&waker);
+
+    let rundown_ref = RundownRef::new();
+    let guard = rundown_ref.try_acquire().unwrap();
+    let mut future = Box::pin(rundown_ref.wait_for_rundown_async());
+
+    // Polling again with the same waker doesn't register it twice.
+    assert!(future.as_mut().poll(&mut context).is_pending());
+    assert!(future.as_mut().poll(&mut context).is_pending());
+    assert_eq!(3, Arc::strong_count(&counter));
+
+    std::mem::drop(future);
+    assert_eq!(2, Arc::strong_count(&counter));
+
+    std::mem::drop(guard);
+    assert!(rundown_ref.is_rundown_complete());
+    assert_eq!(0, counter.0.load(Ordering::SeqCst));
+
+    let rundown_ref = Box::new(RundownRef::new());
+    std::mem::forget(rundown_ref.try_acquire().unwrap());
+    let mut future = Box::pin(rundown_ref.wait_for_rundown_async());
+    assert!(future.as_mut().poll(&mut context).is_pending());
+    std::mem::forget(future);
+    assert_eq!(3, Arc::strong_count(&counter));
+
+    std::mem::drop(rundown_ref);
+    assert_eq!(2, Arc::strong_count(&counter));
+    assert_eq!(0, counter.0.load(Ordering::SeqCst));
+}
+
+//-------------------------------------------------------------------
+// Test: test_rundown_ref_is_word_sized
+//
+// Description:
+//  Test that on Linux, where waiters block on a futex on the reference
+//  count, a RundownRef is no larger than the reference count itself.
+//
+#[test]
+#[cfg(all(target_os = "linux", not(feature = "track-holders")))]
+fn test_rundown_ref_is_word_sized() {
+    assert_eq!(8, std::mem::size_of::<RundownRef>());
+}
+
+//-------------------------------------------------------------------
+// Test: test_acquire_too_many_references
+//
+// Description:
+//  Test that acquiring protection past the maximum reference count
+//  fails with TooManyReferences, without disturbing the rundown flags.
+//
+#[test]
+fn test_acquire_too_many_references() {
+    let rundown_ref = RundownRef::new();
+    let guard = rundown_ref
+        .try_acquire_many(RundownRef::MAX_REF_COUNT - 1)
+        .unwrap();
+    let last = rundown_ref.try_acquire().unwrap();
+
+    assert_eq!(
+        Some(RundownError::TooManyReferences),
+        rundown_ref.try_acquire().err()
+    );
+    assert_eq!(
+        Some(RundownError::TooManyReferences),
+        rundown_ref.try_acquire_many(u64::MAX).err()
+    );
+    assert_eq!(RundownState::Active, rundown_ref.state());
+
+    // Releasing a reference makes room for another one.
+    std::mem::drop(last);
+    let last = rundown_ref.try_acquire().unwrap();
+
+    let result = rundown_ref.wait_for_rundown_timeout(Duration::from_millis(1));
+    assert_eq!(
+        result,
+        RundownWaitResult::TimedOut {
+            outstanding: RundownRef::MAX_REF_COUNT
+        }
+    );
+
+    std::mem::drop(last);
+    std::mem::drop(guard);
+    rundown_ref.wait_for_rundown();
+    assert_eq!(RundownState::RundownComplete, rundown_ref.state());
+    rundown_ref.re_init();
+
+    // The limit doesn't depend on the wait strategy.
+    assert_eq!(run_down::MAX_REF_COUNT, RundownRef::MAX_REF_COUNT);
+    let rundown_ref = RundownRef::with_wait_strategy(SpinWait);
+    let guard = rundown_ref
+        .try_acquire_many(run_down::MAX_REF_COUNT)
+        .unwrap();
+    assert_eq!(
+        Some(RundownError::TooManyReferences),
+        rundown_ref.try_acquire().err()
+    );
+    std::mem::drop(guard);
+}
+
+//-------------------------------------------------------------------
+// Test: test_rundown_ref32_too_many_references
+//
+// Description:
+//  Test that acquiring protection on a RundownRef32 past its maximum
+//  reference count fails with TooManyReferences.
+//
+#[test]
+fn test_rundown_ref32_too_many_references() {
+    let rundown_ref = RundownRef32::new();
+    let guard = rundown_ref
+        .try_acquire_many(RundownRef32::MAX_REF_COUNT)
+        .unwrap();
+
+    assert_eq!(
+        Some(RundownError::TooManyReferences),
+        rundown_ref.try_acquire().err()
+    );
+    assert_eq!(
+        Some(RundownError::TooManyReferences),
+        rundown_ref.try_acquire_many(1000).err()
+    );
+    assert_eq!(RundownState::Active, rundown_ref.state());
+
+    std::mem::drop(guard);
+    rundown_ref.wait_for_rundown();
+    assert_eq!(RundownState::RundownComplete, rundown_ref.state());
+}
+
+//-------------------------------------------------------------------
+// Test: test_error_display_and_error_trait
+//
+// Description:
+//  Test that every RundownError has a distinct message, and that the
+//  errors convert into a boxed std::error::Error with the ? operator.
+//
+#[test]
+fn test_error_display_and_error_trait() {
+    fn acquire_after_rundown() -> Result<(), Box<dyn std::error::Error>> {
+        let rundown_ref = RundownRef::new();
+        rundown_ref.wait_for_rundown();
+        rundown_ref.try_acquire()?;
+        Ok(())
+    }
+
+    let errors = [
+        RundownError::RundownInProgress,
+        RundownError::NotRunDown,
+        RundownError::ReferencesOutstanding,
+        RundownError::TooManyReferences,
+        RundownError::ReferenceUnderflow,
+        RundownError::TooManyTags,
+        RundownError::CallbacksRunning,
+    ];
+
+    let messages: std::collections::HashSet<String> =
+        errors.iter().map(ToString::to_string).collect();
+    assert_eq!(errors.len(), messages.len());
+
+    let unique: std::collections::HashSet<RundownError> = errors.iter().cloned().collect();
+    assert_eq!(errors.len(), unique.len());
+
+    let error = acquire_after_rundown().unwrap_err();
+    assert_eq!(
+        RundownError::RundownInProgress.to_string(),
+        error.to_string()
+    );
+}
+
+//-------------------------------------------------------------------
+// Test: test_try_release_underflow
+//
+// Description:
+//  Test that releasing more references than are outstanding returns
+//  ReferenceUnderflow and leaves the reference count unchanged, and
+//  that releasing the last reference still completes rundown.
+//
+#[test]
+fn test_try_release_underflow() {
+    let rundown_ref = RundownRef::new();
+    assert_eq!(
+        Err(RundownError::ReferenceUnderflow),
+        rundown_ref.try_release()
+    );
+    assert_eq!(RundownState::Active, rundown_ref.state());
+
+    // Take over the references from the guard, to release them by hand.
+    std::mem::forget(rundown_ref.try_acquire_many(2).unwrap());
+    assert_eq!(
+        Err(RundownError::ReferenceUnderflow),
+        rundown_ref.try_release_many(3)
+    );
+
+    let result = rundown_ref.wait_for_rundown_timeout(Duration::from_millis(1));
+    assert_eq!(result, RundownWaitResult::TimedOut { outstanding: 2 });
+
+    assert_eq!(Ok(()), rundown_ref.try_release());
+    assert_eq!(Ok(()), rundown_ref.try_release());
+    assert_eq!(RundownState::RundownComplete, rundown_ref.state());
+    assert_eq!(
+        Err(RundownError::ReferenceUnderflow),
+        rundown_ref.try_release()
+    );
+
+    let rundown_ref = RundownRef32::new();
+    std::mem::forget(rundown_ref.try_acquire().unwrap());
+    assert_eq!(
+        Err(RundownError::ReferenceUnderflow),
+        rundown_ref.try_release_many(2)
+    );
+    assert_eq!(Ok(()), rundown_ref.try_release());
+    assert_eq!(
+        Err(RundownError::ReferenceUnderflow),
+        rundown_ref.try_release()
+    );
+}
+
+//-------------------------------------------------------------------
+// Test: test_guard_by_hand_underflow
+//
+// Description:
+//  Test that dropping a guard created by hand, when no references are
+//  outstanding, panics and leaves the reference count unchanged, and
+//  that it otherwise releases a reference like any other guard.
+//
+#[test]
+fn test_guard_by_hand_underflow() {
+    let rundown_ref = RundownRef::new();
+    let result = std::panic::catch_unwind(|| drop(RundownGuard::new(&rundown_ref)));
+    assert!(result.is_err());
+    assert_eq!(0, rundown_ref.outstanding_refs());
+    assert_eq!(RundownState::Active, rundown_ref.state());
+
+    // Take over the reference from the guard, for the other guard to release.
+    std::mem::forget(rundown_ref.try_acquire().unwrap());
+    drop(RundownGuard::new(&rundown_ref));
+    assert_eq!(0, rundown_ref.outstanding_refs());
+
+    rundown_ref.wait_for_rundown();
+    assert_eq!(RundownState::RundownComplete, rundown_ref.state());
+}
+
+//-------------------------------------------------------------------
+// Test: test_state_introspection
+//
+// Description:
+//  Test that the outstanding references and the progress of rundown
+//  can be observed through the public API, and through Debug.
+//
+#[test]
+fn test_state_introspection() {
+    let rundown_ref = RundownRef::new();
+    assert_eq!(
+        RundownSnapshot {
+            state: RundownState::Active,
+            outstanding_refs: 0
+        },
+        rundown_ref.snapshot()
+    );
+
+    let guard = rundown_ref.try_acquire_many(2).unwrap();
+    assert_eq!(2, rundown_ref.outstanding_refs());
+    assert!(!rundown_ref.is_rundown_in_progress());
+    assert_eq!(
+        "RundownGuard { count: 2, run_down_ref: RundownRef { state: Active, outstanding_refs: 2, .. } }",
+        format!("{:?}", guard)
+    );
+
+    let result = rundown_ref.wait_for_rundown_timeout(Duration::from_millis(1));
+    assert_eq!(result, RundownWaitResult::TimedOut { outstanding: 2 });
+    assert!(rundown_ref.is_rundown_in_progress());
+    assert!(!rundown_ref.is_rundown_complete());
+
+    std::mem::drop(guard);
+    assert!(!rundown_ref.is_rundown_in_progress());
+    assert!(rundown_ref.is_rundown_complete());
+    assert_eq!(
+        "RundownRef { state: RundownComplete, outstanding_refs: 0, .. }",
+        format!("{:?}", rundown_ref)
+    );
+
+    let rundown_ref = RundownRef32::new();
+    let guard = rundown_ref.try_acquire().unwrap();
+    assert_eq!(1, rundown_ref.outstanding_refs());
+    assert_eq!(
+        "RundownGuard32 { count: 1, run_down_ref: RundownRef32 { state: Active, outstanding_refs: 1 } }",
+        format!("{:?}", guard)
+    );
+}
+
+//-------------------------------------------------------------------
+// Test: test_static_rundown_ref
+//
+// Description:
+//  Test that run-down references can be initialized in a static,
+//  and used from many threads at once, like a process-wide shutdown
+//  signal.
+//
+#[test]
+fn test_static_rundown_ref() {
+    static SHUTDOWN: RundownRef = RundownRef::new();
+    static SHUTDOWN32: RundownRef32 = RundownRef32::new();
+    static CONFIG: Rundown<u32> = Rundown::new(42);
+
+    let acquired = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        for _ in 0..16 {
+            scope.spawn(|| {
+                while let Ok(_guard) = SHUTDOWN.try_acquire() {
+                    let _guard32 = SHUTDOWN32.try_acquire();
+                    if let Ok(config) = CONFIG.try_access() {
+                        assert_eq!(42, *config);
+                    }
+
+                    acquired.fetch_add(1, Ordering::Relaxed);
+                    thread::yield_now();
+                }
+            });
+        }
+
+        while acquired.load(Ordering::Relaxed) < 1000 {
+            thread::yield_now();
+        }
+
+        SHUTDOWN32.wait_for_rundown();
+        SHUTDOWN.wait_for_rundown();
+    });
+
+    assert!(SHUTDOWN.is_rundown_complete());
+    assert!(SHUTDOWN32.is_rundown_complete());
+    assert_eq!(0, SHUTDOWN.outstanding_refs());
+}
+
+//-------------------------------------------------------------------
+// Test: test_begin_rundown_two_phase
+//
+// Description:
+//  Test that begin_rundown rejects new protection requests straight
+//  away, without blocking, and that the waiter it returns observes the
+//  outstanding references draining.
+//
+#[test]
+fn test_begin_rundown_two_phase() {
+    let rundown_ref = RundownRef::new();
+    let guard = rundown_ref.try_acquire().unwrap();
+
+    let waiter = rundown_ref.begin_rundown();
+    assert_eq!(
+        Some(RundownError::RundownInProgress),
+        rundown_ref.try_acquire().err()
+    );
+    assert!(!waiter.is_complete());
+    assert_eq!(
+        RundownWaitResult::TimedOut { outstanding: 1 },
+        waiter.try_wait()
+    );
+    assert_eq!(
+        RundownWaitResult::TimedOut { outstanding: 1 },
+        waiter.wait_timeout(Duration::from_millis(1))
+    );
+
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            std::mem::drop(guard);
+        });
+
+        waiter.wait();
+    });
+
+    assert!(waiter.is_complete());
+    assert_eq!(RundownWaitResult::Complete, waiter.try_wait());
+    assert_eq!(
+        RundownWaitResult::Complete,
+        waiter.wait_timeout(Duration::from_millis(1))
+    );
+
+    // The waiter still reports the rundown it was created for as complete
+    // once the object has been re-initialized.
+    rundown_ref.re_init();
+    assert!(waiter.is_complete());
+    waiter.wait();
+}
+
+//-------------------------------------------------------------------
+// Test: test_wait_with_progress
+//
+// Description:
+//  Test that waiting with progress reports the outstanding references
+//  each interval until they are released, and returns how long the
+//  wait took. Without outstanding references nothing is reported.
+//
+#[test]
+fn test_wait_with_progress() {
+    let rundown_ref = RundownRef::new();
+    let mut reports = Vec::new();
+
+    let elapsed =
+        rundown_ref.wait_for_rundown_with_progress(Duration::from_millis(1), |outstanding| {
+            reports.push(outstanding);
+        });
+    assert!(reports.is_empty());
+    assert!(elapsed < Duration::from_secs(1));
+
+    rundown_ref.re_init();
+    let first = rundown_ref.try_acquire().unwrap();
+    let second = rundown_ref.try_acquire().unwrap();
+
+    let elapsed = thread::scope(|scope| {
+        scope.spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            drop(first);
+            thread::sleep(Duration::from_millis(20));
+            drop(second);
+        });
+
+        rundown_ref.wait_for_rundown_with_progress(Duration::from_millis(2), |outstanding| {
+            reports.push(outstanding);
+        })
+    });
+
+    assert_eq!(RundownState::RundownComplete, rundown_ref.state());
+    assert!(elapsed >= Duration::from_millis(40));
+    assert!(reports.contains(&2));
+    assert!(reports.contains(&1));
+    assert!(reports.windows(2).all(|pair| pair[0] >= pair[1]));
+
+    // A zero interval still waits between the reports, instead of spinning.
+    rundown_ref.re_init();
+    let guard = rundown_ref.try_acquire().unwrap();
+    reports.clear();
+
+    let elapsed = thread::scope(|scope| {
+        scope.spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            drop(guard);
+        });
+
+        rundown_ref.wait_for_rundown_with_progress(Duration::ZERO, |outstanding| {
+            reports.push(outstanding);
+        })
+    });
+
+    assert_eq!(RundownState::RundownComplete, rundown_ref.state());
+    assert!(!reports.is_empty());
+    assert!(reports.len() as u128 <= elapsed.as_millis());
+}
+
+//-------------------------------------------------------------------
+// Test: test_wait_timeout_races_last_release
+//
+// Description:
+//  Test that a wait which times out while the last reference is being
+//  released reports rundown as complete, and never reports a timeout
+//  with no outstanding references.
+//
+#[test]
+fn test_wait_timeout_races_last_release() {
+    for _ in 0..200 {
+        let rundown_ref = RundownRef::new();
+        let rundown_ref32 = RundownRef32::new();
+        let guard = rundown_ref.try_acquire().unwrap();
+        let guard32 = rundown_ref32.try_acquire().unwrap();
+
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                thread::sleep(Duration::from_micros(50));
+                drop(guard);
+                drop(guard32);
+            });
+
+            rundown_ref.wait_for_rundown_with_progress(Duration::from_micros(1), |outstanding| {
+                assert_ne!(0, outstanding);
+            });
+
+            while let RundownWaitResult::TimedOut { outstanding } =
+                rundown_ref32.wait_for_rundown_timeout(Duration::from_micros(1))
+            {
+                assert_ne!(0, outstanding);
+            }
+        });
+
+        assert_eq!(RundownState::RundownComplete, rundown_ref.state());
+        assert_eq!(RundownState::RundownComplete, rundown_ref32.state());
+    }
+}
+
+//-------------------------------------------------------------------
+// Test: test_track_holders
+//
+// Description:
+//  Test that with the track-holders feature, every guard holding
+//  protection is listed along with its acquisition, that split guards
+//  share an entry, and that dropped guards are no longer listed.
+//
+#[test]
+#[cfg(feature = "track-holders")]
+fn test_track_holders() {
+    use std::backtrace::BacktraceStatus;
+
+    let rundown_ref = Arc::new(RundownRef::new());
+    assert!(rundown_ref.holders().is_empty());
+
+    let mut guard = rundown_ref.try_acquire_many(3).unwrap();
+    let split = guard.split(1).unwrap();
+    let owned_guard = rundown_ref.try_acquire_owned().unwrap();
+
+    let holders = rundown_ref.holders();
+    assert_eq!(2, holders.len());
+    assert_eq!(
+        vec![3, 1],
+        holders
+            .iter()
+            .map(|holder| holder.count)
+            .collect::<Vec<_>>()
+    );
+    assert!(holders
+        .iter()
+        .all(|holder| holder.thread_id == thread::current().id()));
+    assert!(holders
+        .iter()
+        .all(|holder| holder.backtrace.status() == BacktraceStatus::Captured));
+    assert!(rundown_ref.dump_holders().starts_with("2 holder(s)"));
+
+    drop(split);
+    assert_eq!(2, rundown_ref.holders()[0].count);
+    drop(owned_guard);
+    assert_eq!(1, rundown_ref.holders().len());
+
+    assert_eq!(
+        RundownWaitResult::TimedOut { outstanding: 2 },
+        rundown_ref.wait_for_rundown_or_dump(Duration::from_millis(1))
+    );
+
+    drop(guard);
+    assert!(rundown_ref.holders().is_empty());
+    assert_eq!(
+        RundownWaitResult::Complete,
+        rundown_ref.wait_for_rundown_or_dump(Duration::from_millis(1))
+    );
+}
+
+//-------------------------------------------------------------------
+// Test: test_run_down_then_without_references
+//
+// Description:
+//  Test that a callback registered without outstanding references
+//  runs straight away on the calling thread, and completes rundown.
+//
+#[test]
+fn test_run_down_then_without_references() {
+    let rundown_ref = RundownRef::new();
+    let ran = Arc::new(AtomicUsize::new(0));
+
+    let ran_clone = Arc::clone(&ran);
+    rundown_ref.run_down_then(move || {
+        ran_clone.fetch_add(1, Ordering::SeqCst);
+    });
+
+    assert_eq!(1, ran.load(Ordering::SeqCst));
+    assert_eq!(RundownState::RundownComplete, rundown_ref.state());
+    assert!(rundown_ref.try_acquire().is_err());
+
+    // Callbacks registered once rundown is complete run straight away too.
+    let ran_clone = Arc::clone(&ran);
+    rundown_ref.run_down_then(move || {
+        ran_clone.fetch_add(1, Ordering::SeqCst);
+    });
+    assert_eq!(2, ran.load(Ordering::SeqCst));
+}
+
+//-------------------------------------------------------------------
+// Test: test_run_down_then_runs_on_last_release
+//
+// Description:
+//  Test that callbacks registered while references are outstanding
+//  run once, in order, on the thread which releases the last reference,
+//  and that the object can be re-initialized and run-down again.
+//
+#[test]
+fn test_run_down_then_runs_on_last_release() {
+    let rundown_ref = Arc::new(RundownRef::new());
+    let first = rundown_ref.try_acquire_owned().unwrap();
+    let last = rundown_ref.try_acquire_owned().unwrap();
+
+    let ran = Arc::new(std::sync::Mutex::new(Vec::new()));
+    for index in 0..2 {
+        let ran_clone = Arc::clone(&ran);
+        rundown_ref.run_down_then(move || {
+            ran_clone
+                .lock()
+                .unwrap()
+                .push((index, thread::current().id()));
+        });
+    }
+
+    assert!(ran.lock().unwrap().is_empty());
+    assert_eq!(RundownState::RundownInProgress, rundown_ref.state());
+    assert!(rundown_ref.try_acquire().is_err());
+
+    drop(first);
+    assert!(ran.lock().unwrap().is_empty());
+
+    let releaser = thread::spawn(move || {
+        drop(last);
+        thread::current().id()
+    })
+    .join()
+    .unwrap();
+
+    assert_eq!(vec![(0, releaser), (1, releaser)], *ran.lock().unwrap());
+    assert_eq!(RundownState::RundownComplete, rundown_ref.state());
+
+    // Re-initializing doesn't run the callbacks again.
+    rundown_ref.re_init();
+    let guard = rundown_ref.try_acquire().unwrap();
+    drop(guard);
+    rundown_ref.wait_for_rundown();
+    assert_eq!(2, ran.lock().unwrap().len());
+}
+
+//-------------------------------------------------------------------
+// Test: test_run_down_then_races_release
+//
+// Description:
+//  Test that a callback registered while the last reference is being
+//  released, and while another thread waits for rundown, runs exactly
+//  once, and only once the reference has been released.
+//
+#[test]
+fn test_run_down_then_races_release() {
+    let rundown_ref = Arc::new(RundownRef::new());
+
+    for _ in 0..1000 {
+        let guard = rundown_ref.try_acquire_owned().unwrap();
+        let released = Arc::new(AtomicBool::new(false));
+        let ran = Arc::new(AtomicUsize::new(0));
+
+        let released_clone = Arc::clone(&released);
+        let releaser = thread::spawn(move || {
+            released_clone.store(true, Ordering::SeqCst);
+            drop(guard);
+        });
+
+        let rundown_clone = Arc::clone(&rundown_ref);
+        let waiter = thread::spawn(move || rundown_clone.wait_for_rundown());
+
+        let (released_clone, ran_clone) = (Arc::clone(&released), Arc::clone(&ran));
+        rundown_ref.run_down_then(move || {
+            assert!(released_clone.load(Ordering::SeqCst));
+            ran_clone.fetch_add(1, Ordering::SeqCst);
+        });
+
+        releaser.join().unwrap();
+        waiter.join().unwrap();
+
+        assert_eq!(1, ran.load(Ordering::SeqCst));
+        rundown_ref.re_init();
+    }
+}
+
+//-------------------------------------------------------------------
+// Test: test_re_init_waits_for_run_down_then_callbacks
+//
+// Description:
+//  Test that re-initializing the object waits for the callbacks of
+//  the rundown to finish, and that a callback can't re-initialize it.
+//
+#[test]
+fn test_re_init_waits_for_run_down_then_callbacks() {
+    let rundown_ref = Arc::new(RundownRef::new());
+    let guard = rundown_ref.try_acquire_owned().unwrap();
+    let finished = Arc::new(AtomicBool::new(false));
+    let (started_sender, started) = std::sync::mpsc::channel();
+
+    let finished_clone = Arc::clone(&finished);
+    rundown_ref.run_down_then(move || {
+        started_sender.send(()).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        finished_clone.store(true, Ordering::SeqCst);
+    });
+
+    let (rundown_clone, re_init) = (
+        Arc::clone(&rundown_ref),
+        Arc::new(std::sync::Mutex::new(None)),
+    );
+    let re_init_clone = Arc::clone(&re_init);
+    rundown_ref.run_down_then(move || {
+        *re_init_clone.lock().unwrap() = Some(rundown_clone.try_re_init());
+    });
+
+    let releaser = thread::spawn(move || drop(guard));
+
+    started.recv().unwrap();
+    rundown_ref.re_init();
+    assert!(finished.load(Ordering::SeqCst));
+    assert_eq!(
+        Some(Err(RundownError::CallbacksRunning)),
+        *re_init.lock().unwrap()
+    );
+
+    releaser.join().unwrap();
+    assert_eq!(RundownState::Active, rundown_ref.state());
+}
+
+//-------------------------------------------------------------------
+// Test: test_run_down_then_callback_panics
+//
+// Description:
+//  Test that a callback which panics discards the callbacks after it,
+//  and leaves the object able to be re-initialized.
+//
+#[test]
+fn test_run_down_then_callback_panics() {
+    let rundown_ref = Arc::new(RundownRef::new());
+    let guard = rundown_ref.try_acquire_owned().unwrap();
+    let ran = Arc::new(AtomicUsize::new(0));
+
+    rundown_ref.run_down_then(|| panic!("callback panicked"));
+    let ran_clone = Arc::clone(&ran);
+    rundown_ref.run_down_then(move || {
+        ran_clone.fetch_add(1, Ordering::SeqCst);
+    });
+
+    assert!(thread::spawn(move || drop(guard)).join().is_err());
+    assert_eq!(0, ran.load(Ordering::SeqCst));
+
+    rundown_ref.re_init();
+    assert_eq!(RundownState::Active, rundown_ref.state());
+}
+
+//-------------------------------------------------------------------
+// Test: test_guard_is_rundown_requested
+//
+// Description:
+//  Test that guard holders can observe that rundown has been
+//  requested while they still hold protection.
+//
+#[test]
+fn test_guard_is_rundown_requested() {
+    let rundown_ref = Arc::new(RundownRef::new());
+    let guard = rundown_ref.try_acquire().unwrap();
+    let owned_guard = rundown_ref.try_acquire_owned().unwrap();
+    assert!(!guard.is_rundown_requested());
+    assert!(!owned_guard.is_rundown_requested());
+
+    let waiter = rundown_ref.begin_rundown();
+    assert!(guard.is_rundown_requested());
+    assert!(owned_guard.is_rundown_requested());
+
+    drop(guard);
+    drop(owned_guard);
+    assert!(waiter.is_complete());
+}
+
+//-------------------------------------------------------------------
+// Test: test_expedite_wakes_holders
+//
+// Description:
+//  Test that a holder blocked while holding protection is woken by
+//  its callback when the thread running-down the object expedites
+//  the rundown, so that rundown completes.
+//
+#[test]
+fn test_expedite_wakes_holders() {
+    let rundown_ref = RundownRef::new();
+
+    thread::scope(|scope| {
+        let (acquired_sender, acquired) = std::sync::mpsc::channel();
+        let rundown_ref = &rundown_ref;
+
+        scope.spawn(move || {
+            let guard = rundown_ref.try_acquire().unwrap();
+            let (wake_sender, woken) = std::sync::mpsc::channel();
+            let _registration = guard.on_expedite(move || wake_sender.send(()).unwrap());
+            acquired_sender.send(()).unwrap();
+
+            // Register the callback before checking, so the wake-up can't be missed.
+            while !guard.is_rundown_requested() {
+                woken.recv().unwrap();
+            }
+        });
+
+        acquired.recv().unwrap();
+        let waiter = rundown_ref.begin_rundown();
+        waiter.expedite();
+        waiter.wait();
+    });
+
+    assert_eq!(RundownState::RundownComplete, rundown_ref.state());
+}
+
+//-------------------------------------------------------------------
+// Test: test_expedite_invokes_callbacks_once
+//
+// Description:
+//  Test that expediting invokes each registered callback once, in
+//  the order they were registered in, and that callbacks whose
+//  registration was dropped are not invoked.
+//
+#[test]
+fn test_expedite_invokes_callbacks_once() {
+    fn register<'g>(
+        guard: &'g RundownGuard<'_>,
+        invoked: &Arc<std::sync::Mutex<Vec<usize>>>,
+        index: usize,
+    ) -> ExpediteRegistration<'g> {
+        let invoked_clone = Arc::clone(invoked);
+        guard.on_expedite(move || invoked_clone.lock().unwrap().push(index))
+    }
+
+    let rundown_ref = RundownRef::new();
+    let invoked = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+    let guard = rundown_ref.try_acquire().unwrap();
+    let _first = register(&guard, &invoked, 0);
+    let dropped = register(&guard, &invoked, 1);
+    let _last = register(&guard, &invoked, 2);
+    drop(dropped);
+
+    let waiter = rundown_ref.begin_rundown();
+    waiter.expedite();
+    assert_eq!(vec![0, 2], *invoked.lock().unwrap());
+
+    // Callbacks are only invoked once, and only on this object.
+    let other = RundownRef::new();
+    let other_guard = other.try_acquire().unwrap();
+    let _other = register(&other_guard, &invoked, 3);
+    waiter.expedite();
+    assert_eq!(vec![0, 2], *invoked.lock().unwrap());
+}
+
+/// Counts the number of times it is dropped, for validating that
+/// `DropOnRundown` drops its data exactly once.
+struct DropCounter(Arc<AtomicUsize>);
+
+impl Drop for DropCounter {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+//-------------------------------------------------------------------
+// Test: test_drop_on_rundown_without_guards
+//
+// Description:
+//  Test that retiring a DropOnRundown without outstanding guards drops
+//  the data straight away, and that dropping the container afterwards
+//  doesn't drop it again.
+//
+#[test]
+fn test_drop_on_rundown_without_guards() {
+    let drops = Arc::new(AtomicUsize::new(0));
+    let container = DropOnRundown::new(DropCounter(Arc::clone(&drops)));
+
+    assert!(container.try_acquire().is_ok());
+    assert_eq!(0, drops.load(Ordering::SeqCst));
+
+    container.retire();
+    assert_eq!(1, drops.load(Ordering::SeqCst));
+    assert!(container.is_dropped());
+    assert_eq!(
+        Some(RundownError::RundownInProgress),
+        container.try_acquire().err()
+    );
+
+    container.retire();
+    drop(container);
+    assert_eq!(1, drops.load(Ordering::SeqCst));
+
+    // Data which is never retired is dropped along with the container.
+    let container = DropOnRundown::new(DropCounter(Arc::clone(&drops)));
+    drop(container);
+    assert_eq!(2, drops.load(Ordering::SeqCst));
+}
+
+//-------------------------------------------------------------------
+// Test: test_drop_on_rundown_last_guard_drops
+//
+// Description:
+//  Test that once a DropOnRundown is retired, the data stays reachable
+//  through the outstanding guards, and is dropped by the last of them.
+//
+#[test]
+fn test_drop_on_rundown_last_guard_drops() {
+    let drops = Arc::new(AtomicUsize::new(0));
+    let container = DropOnRundown::new(DropCounter(Arc::clone(&drops)));
+
+    let first = container.try_acquire().unwrap();
+    let last = container.try_acquire().unwrap();
+
+    container.retire();
+    assert_eq!(RundownState::RundownInProgress, container.state());
+    assert!(container.try_acquire().is_err());
+
+    drop(first);
+    assert_eq!(0, drops.load(Ordering::SeqCst));
+    assert!(Arc::ptr_eq(&drops, &last.0));
+
+    drop(last);
+    assert_eq!(1, drops.load(Ordering::SeqCst));
+    assert!(container.is_dropped());
+
+    drop(container);
+    assert_eq!(1, drops.load(Ordering::SeqCst));
+}
+
+//-------------------------------------------------------------------
+// Test: test_drop_on_rundown_races_retire
+//
+// Description:
+//  Test that the data is dropped exactly once when guards are being
+//  acquired and dropped on other threads while the data is retired.
+//
+#[test]
+fn test_drop_on_rundown_races_retire() {
+    for _ in 0..200 {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let container = DropOnRundown::new(DropCounter(Arc::clone(&drops)));
+
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    while let Ok(guard) = container.try_acquire() {
+                        assert_eq!(0, guard.0.load(Ordering::SeqCst));
+                    }
+                });
+            }
+
+            thread::sleep(Duration::from_micros(100));
+            container.retire();
+        });
+
+        assert!(container.is_dropped());
+        assert_eq!(1, drops.load(Ordering::SeqCst));
+    }
+}
+
+//-------------------------------------------------------------------
+// Test: test_tagged_outstanding_by_tag
+//
+// Description:
+//  Test that the references outstanding with each tag are counted
+//  separately, including tags implemented by an enum, and drop back
+//  to zero as their guards are dropped.
+//
+#[test]
+fn test_tagged_outstanding_by_tag() {
+    #[derive(Clone, Copy)]
+    enum Subsystem {
+        Http,
+        Cron,
+    }
+
+    impl RundownTag for Subsystem {
+        fn name(self) -> &'static str {
+            match self {
+                Self::Http => "http",
+                Self::Cron => "cron",
+            }
+        }
+    }
+
+    let rundown_ref = TaggedRundownRef::new();
+    assert_eq!(0, rundown_ref.outstanding_by_tag().count());
+
+    let http1 = rundown_ref.try_acquire_tagged(Subsystem::Http).unwrap();
+    let http2 = rundown_ref.try_acquire_tagged("http").unwrap();
+    let cron = rundown_ref.try_acquire_tagged(Subsystem::Cron).unwrap();
+    assert_eq!("http", http1.tag());
+    assert_eq!("cron", cron.tag());
+    assert_eq!(3, rundown_ref.outstanding_refs());
+
+    let outstanding: Vec<_> = rundown_ref.outstanding_by_tag().collect();
+    assert_eq!(vec![("http", 2), ("cron", 1)], outstanding);
+
+    std::mem::drop(http1);
+    std::mem::drop(http2);
+    let outstanding: Vec<_> = rundown_ref.outstanding_by_tag().collect();
+    assert_eq!(vec![("http", 0), ("cron", 1)], outstanding);
+
+    std::mem::drop(cron);
+    rundown_ref.wait_for_rundown();
+    assert_eq!(RundownState::RundownComplete, rundown_ref.state());
+    assert_eq!(
+        Some(RundownError::RundownInProgress),
+        rundown_ref.try_acquire_tagged("http").err()
+    );
+    assert!(rundown_ref
+        .outstanding_by_tag()
+        .all(|(_, outstanding)| outstanding == 0));
+}
+
+//-------------------------------------------------------------------
+// Test: test_tagged_too_many_tags
+//
+// Description:
+//  Test that acquiring with a new tag fails once the maximum number
+//  of tags are counted, while the existing tags can still be used.
+//
+#[test]
+fn test_tagged_too_many_tags() {
+    const NAMES: [&str; <TaggedRundownRef>::MAX_TAGS] =
+        ["t0", "t1", "t2", "t3", "t4", "t5", "t6", "t7"];
+
+    let rundown_ref = TaggedRundownRef::new();
+
+    for name in NAMES {
+        std::mem::drop(rundown_ref.try_acquire_tagged(name).unwrap());
+    }
+
+    assert_eq!(
+        Some(RundownError::TooManyTags),
+        rundown_ref.try_acquire_tagged("overflow").err()
+    );
+    assert_eq!(0, rundown_ref.outstanding_refs());
+
+    let guard = rundown_ref.try_acquire_tagged("t3").unwrap();
+    let outstanding: Vec<_> = rundown_ref.outstanding_by_tag().collect();
+    assert_eq!(<TaggedRundownRef>::MAX_TAGS, outstanding.len());
+    assert_eq!(("t3", 1), outstanding[3]);
+    std::mem::drop(guard);
+
+    // The limit doesn't depend on the wait strategy.
+    assert_eq!(
+        <TaggedRundownRef>::MAX_TAGS,
+        TaggedRundownRef::<SpinWait>::MAX_TAGS
+    );
+}
+
+//-------------------------------------------------------------------
+// Test: test_tagged_failed_acquire_claims_no_tag
+//
+// Description:
+//  Test that acquiring with a new tag while the object is rundown
+//  doesn't use up one of the slots for the tags.
+//
+#[test]
+fn test_tagged_failed_acquire_claims_no_tag() {
+    const NAMES: [&str; 2 * <TaggedRundownRef>::MAX_TAGS] = [
+        "t0", "t1", "t2", "t3", "t4", "t5", "t6", "t7", "t8", "t9", "t10", "t11", "t12", "t13",
+        "t14", "t15",
+    ];
+
+    let rundown_ref = TaggedRundownRef::new();
+    rundown_ref.wait_for_rundown();
+
+    for name in NAMES {
+        assert_eq!(
+            Some(RundownError::RundownInProgress),
+            rundown_ref.try_acquire_tagged(name).err()
+        );
+    }
+
+    assert_eq!(0, rundown_ref.outstanding_by_tag().count());
+
+    rundown_ref.re_init();
+
+    for name in &NAMES[..<TaggedRundownRef>::MAX_TAGS] {
+        std::mem::drop(rundown_ref.try_acquire_tagged(*name).unwrap());
+    }
+}
+
+//-------------------------------------------------------------------
+// Test: test_tagged_concurrent_tags
+//
+// Description:
+//  Test that threads racing to use the same tags for the first time
+//  each share a single count per tag, and that the counts return to
+//  zero once every guard is dropped.
+//
+#[test]
+fn test_tagged_concurrent_tags() {
+    for _ in 0..50 {
+        let rundown_ref = TaggedRundownRef::new();
+
+        thread::scope(|scope| {
+            for index in 0..8 {
+                let rundown_ref = &rundown_ref;
+                scope.spawn(move || {
+                    let tag = if index % 2 == 0 { "even" } else { "odd" };
+                    for _ in 0..100 {
+                        let guard = rundown_ref.try_acquire_tagged(tag).unwrap();
+                        assert_eq!(tag, guard.tag());
+                    }
+                });
+            }
+        });
+
+        let mut outstanding: Vec<_> = rundown_ref.outstanding_by_tag().collect();
+        outstanding.sort_unstable();
+        assert_eq!(vec![("even", 0), ("odd", 0)], outstanding);
+
+        rundown_ref.wait_for_rundown();
+        assert_eq!(RundownState::RundownComplete, rundown_ref.state());
+    }
+}
+
+//-------------------------------------------------------------------
+// Test: test_rundown_ref32_is_compact
+//
+// Description:
+//  Test that a RundownRef32 is no larger than its 32-bit reference
+//  count, as waiters never need an event embedded in the object.
+//
+#[test]
+fn test_rundown_ref32_is_compact() {
+    assert_eq!(4, std::mem::size_of::<RundownRef32>());
+}
+
+//-------------------------------------------------------------------
+// Test: test_rundown_ref32_acquisition_and_rundown
+//
+// Description:
+//  Test that a RundownRef32 moves through the same states as a
+//  RundownRef as protection is acquired, run-down and released.
+//
+#[test]
+fn test_rundown_ref32_acquisition_and_rundown() {
+    let rundown_ref = RundownRef32::new();
+    assert_eq!(
+        Some(RundownError::NotRunDown),
+        rundown_ref.try_re_init().err()
+    );
+
+    let guard = rundown_ref.try_acquire().unwrap();
+    let guards = rundown_ref.try_acquire_many(3).unwrap();
+    assert_eq!(3, guards.count());
+
+    let result = rundown_ref.wait_for_rundown_timeout(Duration::from_millis(10));
+    assert_eq!(result, RundownWaitResult::TimedOut { outstanding: 4 });
+    assert_eq!(RundownState::RundownInProgress, rundown_ref.state());
+    assert_eq!(
+        Some(RundownError::RundownInProgress),
+        rundown_ref.try_acquire().err()
+    );
+    assert_eq!(
+        Some(RundownError::ReferencesOutstanding),
+        rundown_ref.try_re_init().err()
+    );
+
+    std::mem::drop(guards);
+    std::mem::drop(guard);
+    rundown_ref.wait_for_rundown();
+    assert_eq!(RundownState::RundownComplete, rundown_ref.state());
+
+    rundown_ref.re_init();
+    assert!(rundown_ref.try_acquire().is_ok());
+}
+
+//-------------------------------------------------------------------
+// Test: test_rundown_ref32_dense_table
+//
+// Description:
+//  Test that waiters on many adjacent RundownRef32 instances, which
+//  share wake-up state keyed by address, are all woken once the
+//  protection on their own object is released.
+//
+#[test]
+fn test_rundown_ref32_dense_table() {
+    let table: Vec<RundownRef32> = (0..256).map(|_| RundownRef32::new()).collect();
+
+    thread::scope(|scope| {
+        for rundown_ref in &table {
+            let guard = rundown_ref.try_acquire().unwrap();
+            scope.spawn(move || {
+                thread::yield_now();
+                std::mem::drop(guard);
+            });
+            scope.spawn(move || rundown_ref.wait_for_rundown());
+        }
+    });
+
+    assert!(table
+        .iter()
+        .all(|rundown_ref| rundown_ref.state() == RundownState::RundownComplete));
+}